@@ -0,0 +1,52 @@
+use rustler::{Encoder, Env, Term};
+use rustler::env::OwnedEnv;
+use std::sync::Mutex;
+use threadpool::ThreadPool;
+
+use crate::atoms;
+use crate::locks::lock_safe;
+
+pub const DEFAULT_THREADS: usize = 4;
+
+lazy_static! {
+    static ref POOL: Mutex<ThreadPool> = Mutex::new(
+        ThreadPool::with_name("rocker_async".to_string(), DEFAULT_THREADS)
+    );
+}
+
+pub fn set_threads(num_threads: usize) {
+    lock_safe(&POOL).set_num_threads(num_threads);
+}
+
+// Runs the job on the pool and sends {rocker_reply, Ref, Result}
+// to the calling process, the job builds Result in the message env.
+pub fn reply<'a, F>(env: Env<'a>, reference: Term<'a>, job: F)
+    where F: for<'b> FnOnce(Env<'b>) -> Term<'b> + Send + 'static
+{
+    let pid = env.pid();
+    let mut owned_env = OwnedEnv::new();
+    let reference = owned_env.save(reference);
+    lock_safe(&POOL).execute(move || {
+        owned_env.send_and_clear(&pid, |env| {
+            let result = job(env);
+            (atoms::rocker_reply(), reference.load(env), result).encode(env)
+        });
+    });
+}
+
+// Like reply/3, the job gets a copy of arg in the message env, so
+// decoding it is done on the pool too.
+pub fn reply_with<'a, F>(env: Env<'a>, reference: Term<'a>, arg: Term<'a>, job: F)
+    where F: for<'b> FnOnce(Env<'b>, Term<'b>) -> Term<'b> + Send + 'static
+{
+    let pid = env.pid();
+    let mut owned_env = OwnedEnv::new();
+    let reference = owned_env.save(reference);
+    let arg = owned_env.save(arg);
+    lock_safe(&POOL).execute(move || {
+        owned_env.send_and_clear(&pid, |env| {
+            let result = job(env, arg.load(env));
+            (atoms::rocker_reply(), reference.load(env), result).encode(env)
+        });
+    });
+}
@@ -0,0 +1,127 @@
+use rocksdb::DBIterator;
+use std::collections::BTreeMap;
+
+type KVBytes = (Box<[u8]>, Box<[u8]>);
+
+// a key with the entry it replaced, None if there was none
+type Undo = (Vec<u8>, Option<Option<Vec<u8>>>);
+
+// Staged writes of an indexed batch on the default cf, None is a delete.
+// While savepoints are set, replaced entries are kept to undo them.
+#[derive(Default)]
+pub struct Index {
+    entries: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    undo: Vec<Undo>,
+    savepoints: Vec<usize>,
+}
+
+impl Index {
+    pub fn stage(&mut self, key: &[u8], value: Option<&[u8]>) {
+        let previous = self.entries.insert(key.to_vec(), value.map(|v| v.to_vec()));
+        if !self.savepoints.is_empty() {
+            self.undo.push((key.to_vec(), previous));
+        }
+    }
+
+    // Some(None) when the key is deleted in the batch
+    pub fn get(&self, key: &[u8]) -> Option<Option<&[u8]>> {
+        self.entries.get(key).map(|value| value.as_deref())
+    }
+
+    pub fn set_savepoint(&mut self) {
+        self.savepoints.push(self.undo.len());
+    }
+
+    pub fn rollback_to_savepoint(&mut self) {
+        let mark = match self.savepoints.pop() {
+            Some(mark) => mark,
+            None => return,
+        };
+        while self.undo.len() > mark {
+            let (key, previous) = self.undo.pop().expect("checked above");
+            match previous {
+                Some(value) => {
+                    self.entries.insert(key, value);
+                }
+                None => {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+    }
+
+    // keeps the changes, the undo entries stay for an outer savepoint
+    pub fn pop_savepoint(&mut self) {
+        self.savepoints.pop();
+        if self.savepoints.is_empty() {
+            self.undo.clear();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Index::default();
+    }
+
+    // Entries in iteration order, starting at `from` if given. Reverse
+    // starts at the last key not after it, like a reverse DB iterator.
+    pub fn overlay(&self, from: Option<&[u8]>, reverse: bool) -> Overlay {
+        let entries: Vec<(Vec<u8>, Option<Vec<u8>>)> = match (from, reverse) {
+            (None, false) => self.entries.iter().map(clone_entry).collect(),
+            (None, true) => self.entries.iter().rev().map(clone_entry).collect(),
+            (Some(from), false) => self.entries.range(from.to_vec()..).map(clone_entry).collect(),
+            (Some(from), true) => self.entries.range(..=from.to_vec()).rev().map(clone_entry).collect(),
+        };
+        Overlay {
+            entries,
+            pos: 0,
+            reverse,
+            peeked: None,
+        }
+    }
+}
+
+fn clone_entry(entry: (&Vec<u8>, &Option<Vec<u8>>)) -> (Vec<u8>, Option<Vec<u8>>) {
+    (entry.0.clone(), entry.1.clone())
+}
+
+// A copy of the staged writes when the iterator was created, merged into
+// the DB iterator. Staged entries win over DB ones with the same key.
+pub struct Overlay {
+    entries: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    pos: usize,
+    reverse: bool,
+    peeked: Option<KVBytes>,
+}
+
+impl Overlay {
+    pub fn next(&mut self, iter: &mut DBIterator) -> Option<KVBytes> {
+        loop {
+            if self.peeked.is_none() {
+                self.peeked = iter.next();
+            }
+            let (key, value) = match self.entries.get(self.pos) {
+                Some(entry) => entry,
+                None => return self.peeked.take(),
+            };
+            if let Some((ref db_key, _)) = self.peeked {
+                let db_key: &[u8] = db_key;
+                let db_first = if self.reverse { db_key > &key[..] } else { db_key < &key[..] };
+                if db_first {
+                    return self.peeked.take();
+                }
+                if db_key == &key[..] {
+                    self.peeked = None;
+                }
+            }
+            self.pos += 1;
+            if let Some(value) = value {
+                return Some((key.clone().into_boxed_slice(), value.clone().into_boxed_slice()));
+            }
+        }
+    }
+
+    // entries left besides the ones of the DB iterator
+    pub fn pending(&self) -> bool {
+        self.peeked.is_some() || self.pos < self.entries.len()
+    }
+}
@@ -0,0 +1,79 @@
+use rustler::{Encoder, Env, Term};
+use rustler::types::atom::Atom;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::atoms;
+
+// Legacy callers get {err, Msg} with the message of the RocksDB status,
+// set once from the load info.
+static LEGACY: AtomicBool = AtomicBool::new(false);
+
+pub fn set_legacy(legacy: bool) {
+    LEGACY.store(legacy, Ordering::Relaxed);
+}
+
+fn legacy() -> bool {
+    LEGACY.load(Ordering::Relaxed)
+}
+
+// {error, Reason}, or {err, Reason} in legacy mode
+pub fn error<'a, T: Encoder>(env: Env<'a>, reason: T) -> Term<'a> {
+    if legacy() {
+        (atoms::err(), reason).encode(env)
+    } else {
+        (atoms::error(), reason).encode(env)
+    }
+}
+
+// {error, {Kind, Msg}}, or {err, Msg} in legacy mode
+pub fn with_message<'a>(env: Env<'a>, kind: Atom, msg: String) -> Term<'a> {
+    if legacy() {
+        (atoms::err(), msg).encode(env)
+    } else {
+        (atoms::error(), (kind, msg)).encode(env)
+    }
+}
+
+pub fn corruption<'a>(env: Env<'a>, msg: String) -> Term<'a> {
+    with_message(env, atoms::corruption(), msg)
+}
+
+pub fn invalid_argument<'a>(env: Env<'a>, msg: String) -> Term<'a> {
+    with_message(env, atoms::invalid_argument(), msg)
+}
+
+// RocksDB errors carry Status::ToString(), the status code is its prefix
+pub fn db_error<'a>(env: Env<'a>, e: &rocksdb::Error) -> Term<'a> {
+    status(env, e.to_string())
+}
+
+// a Status::ToString() message as db_error/2 maps it
+pub fn status<'a>(env: Env<'a>, msg: String) -> Term<'a> {
+    if legacy() {
+        return (atoms::err(), msg).encode(env);
+    }
+    let reason = if msg.starts_with("NotFound: ") {
+        (atoms::not_found(), message(&msg, "NotFound: ")).encode(env)
+    } else if msg.starts_with("Corruption: ") {
+        (atoms::corruption(), message(&msg, "Corruption: ")).encode(env)
+    } else if msg.starts_with("IO error: ") {
+        (atoms::io_error(), message(&msg, "IO error: ")).encode(env)
+    } else if msg.starts_with("Invalid argument: ") {
+        (atoms::invalid_argument(), message(&msg, "Invalid argument: ")).encode(env)
+    } else if msg.starts_with("Not implemented: ") {
+        (atoms::not_supported(), message(&msg, "Not implemented: ")).encode(env)
+    } else if msg.starts_with("Resource busy: ") {
+        atoms::busy().encode(env)
+    } else if msg.starts_with("Operation timed out: ") {
+        atoms::timed_out().encode(env)
+    } else if msg.starts_with("Result incomplete: ") {
+        atoms::incomplete().encode(env)
+    } else {
+        (atoms::unknown(), msg).encode(env)
+    };
+    (atoms::error(), reason).encode(env)
+}
+
+fn message(msg: &str, prefix: &str) -> String {
+    msg[prefix.len()..].to_string()
+}
@@ -0,0 +1,109 @@
+// RocksDB reports flushes, background errors and write stalls to an
+// EventListener, a C++ interface the C API of RocksDB 5.14 doesn't expose.
+// The events of the `event_listener` option are polled from properties
+// instead, so the changes of one poll interval come as one event and a
+// flush whose file is compacted away before the next poll can be missed.
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::sst_files::{self, SstFile};
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// memtables kept after their flush, see max_write_buffer_number_to_maintain
+const FLUSHED: &str = "rocksdb.num-immutable-mem-table-flushed";
+const BACKGROUND_ERRORS: &str = "rocksdb.background-errors";
+const WRITE_STOPPED: &str = "rocksdb.is-write-stopped";
+const DELAYED_WRITE_RATE: &str = "rocksdb.actual-delayed-write-rate";
+
+pub enum Event {
+    // the new level 0 files of the column family
+    FlushCompleted { cf: String, files: Vec<SstFile> },
+    // the number of background errors since the db was opened
+    BackgroundError { count: u64 },
+    StallConditionsChanged(Stall),
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Stall {
+    pub write_stopped: bool,
+    // bytes per second, 0 unless writes are delayed
+    pub delayed_write_rate: u64,
+}
+
+#[derive(Default)]
+struct CfState {
+    flushed: u64,
+    // the newest level 0 file seen, flushes write files with higher numbers
+    last_level0: u64,
+}
+
+// What the last poll saw, nothing from before the listener is reported
+pub struct Watched {
+    cfs: BTreeMap<String, CfState>,
+    background_errors: u64,
+    stall: Stall,
+}
+
+impl Watched {
+    // property(cf, name) reads a property of a column family
+    pub fn new<F>(cfs: Vec<String>, property: F) -> Watched
+        where F: Fn(&str, &str) -> Option<String>
+    {
+        let mut watched = Watched {
+            cfs: cfs.into_iter().map(|cf| (cf, CfState::default())).collect(),
+            background_errors: 0,
+            stall: Stall::default(),
+        };
+        watched.poll(property);
+        watched
+    }
+
+    // a created column family has no files yet
+    pub fn add_cf(&mut self, cf: &str) {
+        self.cfs.insert(cf.to_string(), CfState::default());
+    }
+
+    pub fn remove_cf(&mut self, cf: &str) {
+        self.cfs.remove(cf);
+    }
+
+    pub fn poll<F>(&mut self, property: F) -> Vec<Event>
+        where F: Fn(&str, &str) -> Option<String>
+    {
+        let mut events = Vec::new();
+        for (cf, state) in self.cfs.iter_mut() {
+            let flushed = number(property(cf, FLUSHED));
+            let files: Vec<SstFile> = property(cf, sst_files::PROPERTY)
+                .and_then(|listing| sst_files::parse(&listing).ok())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|file| file.level == 0 && file.number > state.last_level0)
+                .collect();
+            if flushed > state.flushed || !files.is_empty() {
+                state.last_level0 = files.iter().map(|file| file.number).max().unwrap_or(state.last_level0);
+                events.push(Event::FlushCompleted { cf: cf.clone(), files });
+            }
+            state.flushed = flushed;
+        }
+        let background_errors = number(property("default", BACKGROUND_ERRORS));
+        if background_errors > self.background_errors {
+            events.push(Event::BackgroundError { count: background_errors });
+        }
+        self.background_errors = background_errors;
+        let stall = Stall {
+            write_stopped: number(property("default", WRITE_STOPPED)) != 0,
+            delayed_write_rate: number(property("default", DELAYED_WRITE_RATE)),
+        };
+        if stall != self.stall {
+            events.push(Event::StallConditionsChanged(stall));
+        }
+        self.stall = stall;
+        events
+    }
+}
+
+// a missing property reads as 0
+fn number(value: Option<String>) -> u64 {
+    value.and_then(|value| value.trim().parse().ok()).unwrap_or(0)
+}
@@ -0,0 +1,90 @@
+use rocksdb::{Options, WriteBatch, DB};
+
+use crate::write_batch::{self, BatchOp};
+
+// Records of rocker itself live in a column family of their own, created
+// on first use. It's hidden from the cf functions and from wal_next, and
+// every open function opens it along with the other ones.
+pub const NAME: &str = "$rocker";
+
+// last leader seqno written by apply_batch_data/3
+pub const APPLIED_SEQNO: &[u8] = b"applied_seqno";
+
+// a prepared tx is this prefix and its name, holding the batch data
+pub const PREPARED: &[u8] = b"prepared/";
+
+const ID: &[u8] = b"cf_id";
+
+pub fn prepared_key(name: &[u8]) -> Vec<u8> {
+    [PREPARED, name].concat()
+}
+
+pub fn exists(path: &str) -> bool {
+    match DB::list_cf(&Options::default(), path) {
+        Ok(cfs) => cfs.iter().any(|cf| cf == NAME),
+        Err(_) => false,
+    }
+}
+
+// Errors are RocksDB status messages, as errors::status/2 takes them
+pub fn create(db: &mut DB) -> Result<u32, String> {
+    db.create_cf(NAME, &Options::default()).map_err(|e| e.to_string())?;
+    match id(db)? {
+        Some(id) => Ok(id),
+        None => Err("internal column family not created".to_string()),
+    }
+}
+
+// The id the log refers to the cf by, None without the cf. The bindings
+// only show it in the log, so it's read back from a write of its own the
+// first time and stored. Nothing else may write meanwhile.
+pub fn id(db: &DB) -> Result<Option<u32>, String> {
+    let handle = match db.cf_handle(NAME) {
+        Some(handle) => handle,
+        None => return Ok(None),
+    };
+    let status = |e: rocksdb::Error| e.to_string();
+    if let Some(stored) = db.get_cf(handle, ID).map_err(status)? {
+        if stored.len() == 4 {
+            let mut id = [0u8; 4];
+            id.copy_from_slice(&stored);
+            return Ok(Some(u32::from_be_bytes(id)));
+        }
+    }
+    let mut batch = WriteBatch::default();
+    batch.put_cf(handle, ID, b"").map_err(status)?;
+    db.write(batch).map_err(status)?;
+    let written = db.get_updates_since(db.latest_sequence_number()).map_err(status)?
+        .next()
+        .and_then(|(_, batch)| find_id(batch.data()));
+    let id = match written {
+        Some(id) => id,
+        None => return Err("internal column family id not in the log".to_string()),
+    };
+    db.put_cf(handle, ID, &id.to_be_bytes()).map_err(status)?;
+    Ok(Some(id))
+}
+
+fn find_id(data: &[u8]) -> Option<u32> {
+    write_batch::parse(data).ok()?.iter().filter_map(|op| match *op {
+        BatchOp::Put(cf, key, _) if key == ID => Some(cf),
+        _ => None,
+    }).next()
+}
+
+// None for a missing or malformed marker
+pub fn decode_seqno(value: Option<&[u8]>) -> Option<u64> {
+    let value = value?;
+    if value.len() != 8 {
+        return None;
+    }
+    let mut seqno = [0u8; 8];
+    seqno.copy_from_slice(value);
+    Some(u64::from_be_bytes(seqno))
+}
+
+pub fn cf_of(op: &BatchOp) -> u32 {
+    match *op {
+        BatchOp::Put(cf, _, _) | BatchOp::Delete(cf, _) | BatchOp::Merge(cf, _, _) | BatchOp::DeleteRange(cf, _, _) => cf,
+    }
+}
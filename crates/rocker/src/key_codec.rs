@@ -0,0 +1,256 @@
+use rustler::{Encoder, Env, Error, NifResult, Term};
+use rustler::types::atom::Atom;
+use rustler::types::binary::{Binary, OwnedBinary};
+use std::borrow::Cow;
+
+// Type tags follow the Erlang term order:
+// number < atom < tuple < list < binary
+const TAG_NUMBER: u8 = 0x10;
+const TAG_ATOM: u8 = 0x20;
+const TAG_TUPLE: u8 = 0x50;
+const TAG_LIST: u8 = 0x60;
+const TAG_BINARY: u8 = 0x70;
+
+// Numbers share one tag so that integers and floats interleave by value.
+// The order key of the value as a float comes first, the subtype settles
+// ties between an integer and its (possibly rounded) float image.
+const NUM_INT_BELOW: u8 = 0x00;
+const NUM_FLOAT: u8 = 0x01;
+const NUM_INT_EQUAL: u8 = 0x02;
+const NUM_INT_ABOVE: u8 = 0x03;
+
+// Escaping for variable length byte strings, 0x00 never appears unescaped
+// so the terminator sorts before any continuation.
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xFF;
+const TERMINATOR: u8 = 0x01;
+
+// List end marker, sorts before any element tag.
+const LIST_END: u8 = 0x00;
+
+// Tuples and lists nested deeper are a badarg, the codec recurses on
+// the scheduler's stack.
+const MAX_DEPTH: usize = 100;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum KeyCodec {
+    Raw,
+    SortableTerm,
+}
+
+impl KeyCodec {
+    pub fn from_atom(name: &str) -> Option<KeyCodec> {
+        match name {
+            "raw" => Some(KeyCodec::Raw),
+            "sortable_term" => Some(KeyCodec::SortableTerm),
+            _ => None,
+        }
+    }
+
+    pub fn encode<'a>(&self, term: Term<'a>) -> NifResult<Cow<'a, [u8]>> {
+        match *self {
+            KeyCodec::Raw => {
+                let bin: Binary<'a> = term.decode()?;
+                Ok(Cow::Borrowed(bin.as_slice()))
+            }
+            KeyCodec::SortableTerm => Ok(Cow::Owned(encode(term)?)),
+        }
+    }
+
+    pub fn decode<'a>(&self, env: Env<'a>, bytes: &[u8]) -> NifResult<Term<'a>> {
+        match *self {
+            KeyCodec::Raw => {
+                let mut bin = OwnedBinary::new(bytes.len()).unwrap();
+                bin.clone_from_slice(bytes);
+                Ok(bin.release(env).encode(env))
+            }
+            KeyCodec::SortableTerm => decode(env, bytes),
+        }
+    }
+}
+
+pub fn encode<'a>(term: Term<'a>) -> NifResult<Vec<u8>> {
+    let mut out = Vec::new();
+    encode_term(term, &mut out, 0)?;
+    Ok(out)
+}
+
+pub fn decode<'a>(env: Env<'a>, bytes: &[u8]) -> NifResult<Term<'a>> {
+    let mut pos = 0;
+    let term = decode_term(env, bytes, &mut pos, 0)?;
+    if pos != bytes.len() {
+        return Err(Error::BadArg);
+    }
+    Ok(term)
+}
+
+fn encode_term<'a>(term: Term<'a>, out: &mut Vec<u8>, depth: usize) -> NifResult<()> {
+    if depth > MAX_DEPTH {
+        return Err(Error::BadArg);
+    }
+    if term.is_number() {
+        if let Ok(i) = term.decode::<i64>() {
+            encode_int(i, out);
+        } else {
+            let f: f64 = term.decode()?;
+            out.push(TAG_NUMBER);
+            out.extend_from_slice(&float_order_key(f));
+            out.push(NUM_FLOAT);
+        }
+    } else if term.is_atom() {
+        let name = term.atom_to_string()?;
+        out.push(TAG_ATOM);
+        encode_bytes(name.as_bytes(), out);
+    } else if term.is_tuple() {
+        let elems = ::rustler::types::tuple::get_tuple(term)?;
+        out.push(TAG_TUPLE);
+        out.extend_from_slice(&(elems.len() as u32).to_be_bytes());
+        for elem in elems {
+            encode_term(elem, out, depth + 1)?;
+        }
+    } else if term.is_empty_list() || term.is_list() {
+        // cell by cell, an improper tail is a badarg instead of dropped
+        let mut tail = term;
+        out.push(TAG_LIST);
+        while !tail.is_empty_list() {
+            let (elem, rest) = tail.list_get_cell()?;
+            encode_term(elem, out, depth + 1)?;
+            tail = rest;
+        }
+        out.push(LIST_END);
+    } else if term.is_binary() {
+        let bin: Binary = term.decode()?;
+        out.push(TAG_BINARY);
+        encode_bytes(bin.as_slice(), out);
+    } else {
+        return Err(Error::BadArg);
+    }
+    Ok(())
+}
+
+fn encode_int(i: i64, out: &mut Vec<u8>) {
+    let image = i as f64;
+    // the image of an i64 is integral and within i128, so this is exact
+    let image_int = image as i128;
+    let subtype = if (i as i128) < image_int {
+        NUM_INT_BELOW
+    } else if (i as i128) > image_int {
+        NUM_INT_ABOVE
+    } else {
+        NUM_INT_EQUAL
+    };
+    out.push(TAG_NUMBER);
+    out.extend_from_slice(&float_order_key(image));
+    out.push(subtype);
+    out.extend_from_slice(&((i as u64) ^ (1 << 63)).to_be_bytes());
+}
+
+fn float_order_key(f: f64) -> [u8; 8] {
+    let bits = f.to_bits();
+    let key = if bits >> 63 == 1 { !bits } else { bits ^ (1 << 63) };
+    key.to_be_bytes()
+}
+
+fn float_from_order_key(key: u64) -> f64 {
+    let bits = if key >> 63 == 1 { key ^ (1 << 63) } else { !key };
+    f64::from_bits(bits)
+}
+
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    for &b in bytes {
+        if b == ESCAPE {
+            out.push(ESCAPE);
+            out.push(ESCAPED_ZERO);
+        } else {
+            out.push(b);
+        }
+    }
+    out.push(ESCAPE);
+    out.push(TERMINATOR);
+}
+
+fn decode_term<'a>(env: Env<'a>, bytes: &[u8], pos: &mut usize, depth: usize) -> NifResult<Term<'a>> {
+    if depth > MAX_DEPTH {
+        return Err(Error::BadArg);
+    }
+    let tag = take(bytes, pos, 1)?[0];
+    match tag {
+        TAG_NUMBER => {
+            let key = u64::from_be_bytes(take_array(bytes, pos)?);
+            let subtype = take(bytes, pos, 1)?[0];
+            match subtype {
+                NUM_FLOAT => Ok(float_from_order_key(key).encode(env)),
+                NUM_INT_BELOW | NUM_INT_EQUAL | NUM_INT_ABOVE => {
+                    let raw = u64::from_be_bytes(take_array(bytes, pos)?);
+                    Ok(((raw ^ (1 << 63)) as i64).encode(env))
+                }
+                _ => Err(Error::BadArg),
+            }
+        }
+        TAG_ATOM => {
+            let raw = decode_bytes(bytes, pos)?;
+            let name = String::from_utf8(raw).map_err(|_| Error::BadArg)?;
+            Ok(Atom::from_str(env, &name)?.encode(env))
+        }
+        TAG_TUPLE => {
+            let mut raw = [0u8; 4];
+            raw.copy_from_slice(take(bytes, pos, 4)?);
+            let arity = u32::from_be_bytes(raw) as usize;
+            // every element takes a byte at least, the arity isn't trusted
+            let mut elems = Vec::with_capacity(arity.min(bytes.len() - *pos));
+            for _ in 0..arity {
+                elems.push(decode_term(env, bytes, pos, depth + 1)?);
+            }
+            Ok(::rustler::types::tuple::make_tuple(env, &elems))
+        }
+        TAG_LIST => {
+            let mut elems = Vec::new();
+            loop {
+                if *bytes.get(*pos).ok_or(Error::BadArg)? == LIST_END {
+                    *pos += 1;
+                    break;
+                }
+                elems.push(decode_term(env, bytes, pos, depth + 1)?);
+            }
+            Ok(elems.encode(env))
+        }
+        TAG_BINARY => {
+            let raw = decode_bytes(bytes, pos)?;
+            let mut bin = OwnedBinary::new(raw.len()).unwrap();
+            bin.clone_from_slice(&raw);
+            Ok(bin.release(env).encode(env))
+        }
+        _ => Err(Error::BadArg),
+    }
+}
+
+fn decode_bytes(bytes: &[u8], pos: &mut usize) -> NifResult<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let b = take(bytes, pos, 1)?[0];
+        if b != ESCAPE {
+            out.push(b);
+            continue;
+        }
+        match take(bytes, pos, 1)?[0] {
+            ESCAPED_ZERO => out.push(0),
+            TERMINATOR => return Ok(out),
+            _ => return Err(Error::BadArg),
+        }
+    }
+}
+
+fn take<'b>(bytes: &'b [u8], pos: &mut usize, len: usize) -> NifResult<&'b [u8]> {
+    if bytes.len() < *pos + len {
+        return Err(Error::BadArg);
+    }
+    let slice = &bytes[*pos..*pos + len];
+    *pos += len;
+    Ok(slice)
+}
+
+fn take_array(bytes: &[u8], pos: &mut usize) -> NifResult<[u8; 8]> {
+    let mut arr = [0u8; 8];
+    arr.copy_from_slice(take(bytes, pos, 8)?);
+    Ok(arr)
+}
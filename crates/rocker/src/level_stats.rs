@@ -0,0 +1,64 @@
+use rocksdb::Error;
+
+// RocksDB reports the table properties of a level summed over its files,
+// as TableProperties::ToString() prints them: "# data blocks=1; # entries=10;
+// raw key size=80; ...". The names below are the ones of RocksDB 5.14, which
+// has "(estimated) table size" but no "# deletions". Later versions add
+// details to some names, as "index block size (user-key? 0, delta-value? 0)",
+// so a name also matches with such a suffix.
+const FILES_AT_LEVEL: &str = "rocksdb.num-files-at-level";
+const TABLE_PROPERTIES_AT_LEVEL: &str = "rocksdb.aggregated-table-properties-at-level";
+
+pub struct LevelStats {
+    pub level: usize,
+    pub files: u64,
+    pub size: u64,
+    pub entries: u64,
+    // not reported by every RocksDB version
+    pub deletions: Option<u64>,
+}
+
+// Levels from 0 up to the last one the cf is configured with,
+// `property` reads a property of the cf.
+pub fn collect<F>(property: F) -> Result<Vec<LevelStats>, Error>
+    where F: Fn(&str) -> Result<Option<String>, Error>
+{
+    let mut levels = Vec::new();
+    loop {
+        let level = levels.len();
+        let files = match property(&format!("{}{}", FILES_AT_LEVEL, level))? {
+            Some(files) => parse_u64(&files),
+            None => break,
+        };
+        let properties = property(&format!("{}{}", TABLE_PROPERTIES_AT_LEVEL, level))?.unwrap_or_default();
+        let value = |name: &str| find(&properties, name).map(parse_u64);
+        let size = match value("(estimated) table size") {
+            Some(size) => size,
+            None => ["data block size", "index block size", "filter block size"].iter()
+                .map(|name| value(name).unwrap_or(0))
+                .sum(),
+        };
+        levels.push(LevelStats {
+            level,
+            files,
+            size,
+            entries: value("# entries").unwrap_or(0),
+            deletions: value("# deletions"),
+        });
+    }
+    Ok(levels)
+}
+
+fn find<'a>(properties: &'a str, name: &str) -> Option<&'a str> {
+    properties.split(';')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            Some((parts.next()?.trim(), parts.next()?.trim()))
+        })
+        .find(|&(key, _)| key == name || key.starts_with(name) && key[name.len()..].starts_with(" ("))
+        .map(|(_, value)| value)
+}
+
+fn parse_u64(value: &str) -> u64 {
+    value.trim().parse().unwrap_or(0)
+}
@@ -1,34 +1,395 @@
+#[macro_use]
 extern crate lazy_static;
 extern crate rocksdb;
 #[macro_use]
 extern crate rustler;
+extern crate threadpool;
 
-use rocksdb::{DB, DBCompactionStyle, Direction, IteratorMode, Options, WriteBatch};
-use rocksdb::DBIterator;
+use rocksdb::{DB, Direction, IteratorMode, Options, WriteBatch, WriteOptions};
+use rocksdb::{ColumnFamily, ColumnFamilyDescriptor, DBIterator, DBPinnableSlice, DBWALIterator};
 use rustler::{Encoder, Env, NifResult, Term};
+use rustler::env::OwnedEnv;
 use rustler::resource::ResourceArc;
 use rustler::schedule::SchedulerFlags;
 use rustler::types::binary::{Binary, OwnedBinary};
 use rustler::types::list::ListIterator;
-use rustler::types::map::MapIterator;
-use std::sync::RwLock;
+use rustler::types::LocalPid;
+use rustler::types::map::{map_new, MapIterator};
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+use std::thread;
+
+mod async_pool;
+mod batch_index;
+mod errors;
+mod events;
+mod internal;
+mod key_codec;
+mod level_stats;
+mod locks;
+mod options;
+mod sst_files;
+mod timeslice;
+mod value_codec;
+mod write_batch;
+
+use batch_index::{Index, Overlay};
+use key_codec::KeyCodec;
+use locks::{lock_safe, KeyLocks, RwLockExt};
+use timeslice::Timeslice;
+use value_codec::ValueCodec;
 
 mod atoms {
     rustler_atoms! {
         atom ok;
         atom vn1;
         atom err;
+        atom error;
+        atom not_found;
+        atom corruption;
+        atom io_error;
+        atom busy;
+        atom timed_out;
+        atom invalid_argument;
+        atom incomplete;
+        atom not_supported;
+        atom unknown;
+        atom unknown_cf;
+        atom unknown_option;
+        atom invalid_option;
         atom notfound;
+        atom put;
+        atom delete;
+        atom merge;
+        atom delete_range;
+        atom skipped;
+        atom rocker_reply;
+        atom async_threads;
+        atom error_format;
+        atom closed;
+        atom flush;
+        atom cf_dropped;
+        atom consumed;
+        atom bad_op;
+        atom prepared;
+        atom mismatch;
+        atom name;
+        atom size;
+        atom levels;
+        atom level;
+        atom files;
+        atom entries;
+        atom deletions;
+        atom sst_files;
+        atom column_family;
+        atom smallest_key;
+        atom largest_key;
+        atom rocker_event;
+        atom flush_completed;
+        atom background_error;
+        atom stall_conditions_changed;
+        atom count;
+        atom write_stopped;
+        atom delayed_write_rate;
+        atom immutable_option;
+        atom undefined;
+        atom badarg;
+        atom continue_ = "continue";
     }
 }
 
+// Iterators are shared with the DbResource they come from,
+// close/1 empties them before the DB itself is dropped.
+type Shared<T> = Arc<RwLock<Option<T>>>;
+
 struct DbResource {
-    db: RwLock<DB>,
+    // RocksDB is thread safe, reads and writes share the read lock.
+    // The write lock is for create_cf/drop_cf, which need &mut DB, and close.
+    db: RwLock<Option<DB>>,
     path: String,
+    key_codec: KeyCodec,
+    value_codec: ValueCodec,
+    cf_value_codecs: RwLock<HashMap<String, ValueCodec>>,
+    iterators: Mutex<Vec<Tracked<DBIterator>>>,
+    wal_iterators: Mutex<Vec<Tracked<DBWALIterator>>>,
+    cf_handles: Mutex<Vec<Tracked<CfHandle>>>,
+    apply_lock: Mutex<()>,
+    // log id of the internal cf, None until it's created
+    internal_cf: RwLock<Option<u32>>,
+    // prepared tx records are checked and changed under it
+    prepare_lock: Mutex<()>,
+    // conditional writes of a key are serialized on it
+    key_locks: KeyLocks,
+    // raw values of at least this size are served as resource binaries
+    binary_threshold: usize,
+    pins: Mutex<Pins>,
+    // polled for the event_listener option, None without one
+    watched: Mutex<Option<events::Watched>>,
+}
+
+// Pinned slices point into the memtable or block cache, close/1 moves the
+// db here until the last resource binary over one of them is collected.
+struct Pins {
+    count: usize,
+    retired: Option<DB>,
+}
+
+impl DbResource {
+    fn new(db: DB, path: String, key_codec: KeyCodec, value_codec: ValueCodec, binary_threshold: usize) -> DbResource {
+        DbResource {
+            db: RwLock::new(
+                Some(db)
+            ),
+            path,
+            key_codec,
+            value_codec,
+            cf_value_codecs: RwLock::new(HashMap::new()),
+            iterators: Mutex::new(Vec::new()),
+            wal_iterators: Mutex::new(Vec::new()),
+            cf_handles: Mutex::new(Vec::new()),
+            apply_lock: Mutex::new(()),
+            internal_cf: RwLock::new(None),
+            prepare_lock: Mutex::new(()),
+            key_locks: KeyLocks::default(),
+            binary_threshold,
+            pins: Mutex::new(Pins { count: 0, retired: None }),
+            watched: Mutex::new(None),
+        }
+    }
+
+    // None once the db is closed
+    fn read_db(&self) -> Option<DbRead<'_>> {
+        let guard = self.db.read_safe();
+        if guard.is_some() { Some(DbRead(guard)) } else { None }
+    }
+
+    fn write_db(&self) -> Option<DbWrite<'_>> {
+        let guard = self.db.write_safe();
+        if guard.is_some() { Some(DbWrite(guard)) } else { None }
+    }
+
+    // Creates the internal cf on first use, None once the db is closed.
+    // Takes the db write lock, so it's called before read_db.
+    fn internal_cf(&self) -> Option<Result<u32, String>> {
+        if let Some(id) = *self.internal_cf.read_safe() {
+            return Some(Ok(id));
+        }
+        let mut db = self.write_db()?;
+        let mut internal_cf = self.internal_cf.write_safe();
+        if let Some(id) = *internal_cf {
+            return Some(Ok(id));
+        }
+        let created = internal::create(&mut db);
+        if let Ok(id) = created {
+            *internal_cf = Some(id);
+        }
+        Some(created)
+    }
+
+    // column families without an own codec inherit the db one
+    fn cf_value_codec(&self, cf: &str) -> ValueCodec {
+        match self.cf_value_codecs.read_safe().get(cf) {
+            Some(codec) => *codec,
+            None => self.value_codec,
+        }
+    }
+
+    fn track_iterator(&self, iter: DBIterator, cf: Option<&str>) -> Shared<DBIterator> {
+        track(&self.iterators, iter, cf)
+    }
+
+    fn track_wal_iterator(&self, iter: DBWALIterator) -> Shared<DBWALIterator> {
+        track(&self.wal_iterators, iter, None)
+    }
+
+    fn track_cf_handle(&self, handle: CfHandle, cf: &str) -> Shared<CfHandle> {
+        track(&self.cf_handles, handle, Some(cf))
+    }
+
+    // called under the db read lock, so close/1 sees the pin
+    fn pin(&self) {
+        lock_safe(&self.pins).count += 1;
+    }
+
+    // The retired db is closed under the lock, so released/1 doesn't see
+    // it gone before its files are.
+    fn unpin(&self) {
+        let mut pins = lock_safe(&self.pins);
+        pins.count -= 1;
+        if pins.count == 0 {
+            pins.retired = None;
+        }
+    }
+}
+
+struct Tracked<T> {
+    cf: Option<String>,
+    shared: Weak<RwLock<Option<T>>>,
+}
+
+fn track<T>(list: &Mutex<Vec<Tracked<T>>>, value: T, cf: Option<&str>) -> Shared<T> {
+    let shared = Arc::new(RwLock::new(Some(value)));
+    let mut list = lock_safe(list);
+    list.retain(|tracked| tracked.shared.upgrade().is_some());
+    list.push(Tracked {
+        cf: cf.map(|cf| cf.to_string()),
+        shared: Arc::downgrade(&shared),
+    });
+    shared
+}
+
+// empties everything, or only what was created on the given cf
+fn invalidate<T>(list: &Mutex<Vec<Tracked<T>>>, cf: Option<&str>) {
+    let mut list = lock_safe(list);
+    let (matching, rest): (Vec<Tracked<T>>, Vec<Tracked<T>>) = list.drain(..)
+        .partition(|tracked| cf.is_none() || tracked.cf.as_deref() == cf);
+    *list = rest;
+    for tracked in matching {
+        if let Some(shared) = tracked.shared.upgrade() {
+            shared.write_safe().take();
+        }
+    }
+}
+
+struct DbRead<'a>(RwLockReadGuard<'a, Option<DB>>);
+
+impl<'a> Deref for DbRead<'a> {
+    type Target = DB;
+
+    fn deref(&self) -> &DB {
+        self.0.as_ref().expect("db checked in read_db")
+    }
+}
+
+struct DbWrite<'a>(RwLockWriteGuard<'a, Option<DB>>);
+
+impl<'a> Deref for DbWrite<'a> {
+    type Target = DB;
+
+    fn deref(&self) -> &DB {
+        self.0.as_ref().expect("db checked in write_db")
+    }
+}
+
+impl<'a> DerefMut for DbWrite<'a> {
+    fn deref_mut(&mut self) -> &mut DB {
+        self.0.as_mut().expect("db checked in write_db")
+    }
 }
 
+// Fields drop in order, the iterator goes before the db it keeps alive.
 struct IteratorResource {
-    iter: RwLock<DBIterator>,
+    iter: Shared<DBIterator>,
+    db: ResourceArc<DbResource>,
+    cf: Option<String>,
+    key_codec: KeyCodec,
+    value_codec: ValueCodec,
+    // staged writes merged in by wbwi_iterator/3
+    overlay: Option<Mutex<Overlay>>,
+}
+
+impl IteratorResource {
+    // an emptied iterator was invalidated by close/1 or drop_cf/2
+    fn invalidated<'a>(&self, env: Env<'a>) -> Term<'a> {
+        if self.cf.is_some() && self.db.read_db().is_some() {
+            errors::error(env, atoms::cf_dropped())
+        } else {
+            closed(env)
+        }
+    }
+}
+
+// Raw handles are used under the db read lock only, and emptied under
+// the write lock before drop_cf/close destroy them.
+struct CfHandle(ColumnFamily);
+
+unsafe impl Sync for CfHandle {}
+
+struct CfResource {
+    handle: Shared<CfHandle>,
+    db: ResourceArc<DbResource>,
+    name: String,
+    value_codec: ValueCodec,
+}
+
+// The batch of a tx/2 between two calls of tx_slice/3
+struct PendingBatch {
+    builder: Mutex<Option<TxBuilder>>,
+}
+
+// A batch built over several calls, written by write_batch/3.
+// Operations on cf handles tie it to the db of the handles.
+struct BatchResource {
+    // created by wbwi_new/0
+    indexed: bool,
+    state: Mutex<BatchState>,
+}
+
+impl BatchResource {
+    fn new(indexed: bool) -> BatchResource {
+        BatchResource {
+            indexed,
+            state: Mutex::new(BatchState {
+                batch: Some(WriteBatch::default()),
+                index: if indexed { Some(Index::default()) } else { None },
+                db: None,
+                name: None,
+                prepared: false,
+            }),
+        }
+    }
+}
+
+struct BatchState {
+    // None once written
+    batch: Option<WriteBatch>,
+    // staged writes of an indexed batch, until it's written
+    index: Option<Index>,
+    db: Option<ResourceArc<DbResource>>,
+    // set by tx_set_name/2, a prepared batch is stored under it
+    name: Option<Vec<u8>>,
+    prepared: bool,
+}
+
+struct WalIteratorResource {
+    iter: Shared<DBWALIterator>,
+    db: ResourceArc<DbResource>,
+}
+
+// Backs a resource binary, fields drop in order, the slice goes before
+// the pin that keeps its db open.
+struct ValueResource {
+    bytes: ValueBytes,
+    _pin: Option<Pin>,
+}
+
+enum ValueBytes {
+    Pinned(DBPinnableSlice<'static>),
+    Boxed(Box<[u8]>),
+}
+
+// Pinned slices are read only, RocksDB releases them from any thread.
+unsafe impl Send for ValueBytes {}
+unsafe impl Sync for ValueBytes {}
+
+impl ValueBytes {
+    fn as_slice(&self) -> &[u8] {
+        match *self {
+            ValueBytes::Pinned(ref slice) => slice,
+            ValueBytes::Boxed(ref bytes) => bytes,
+        }
+    }
+}
+
+struct Pin(ResourceArc<DbResource>);
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        self.0.unpin();
+    }
 }
 
 rustler_export_nifs!(
@@ -38,13 +399,14 @@ rustler_export_nifs!(
         ("open", 2, open), // open db with options
         ("open_default", 1, open_default), // open db with defaults
         ("open_cf_default", 2, open_cf_default), // open db with default options and cfs
+        ("open_cf", 3, open_cf), // open db and its cfs with options
         ("destroy", 1 , destroy, SchedulerFlags::DirtyIo), //destroy db and data
         ("repair", 1 , repair, SchedulerFlags::DirtyIo), //repair db
         ("path", 1, path), //get fs path
         ("put", 3, put), //put key payload
         ("get", 2, get), //get key payload
         ("delete", 2, delete), //delete key
-        ("tx", 2, tx), //atomic write batch
+        ("tx_slice", 3, tx_slice), // add ops to a batch until the timeslice is used, then write it
         ("iterator", 2, iterator), // get db iterator
         ("prefix_iterator", 2, prefix_iterator), // get prefix iterator
         ("iterator_valid", 1, iterator_valid), // validate iterator
@@ -56,16 +418,91 @@ rustler_export_nifs!(
         ("put_cf", 4, put_cf), //put key payload into cf
         ("get_cf", 3, get_cf), //get key payload from cf
         ("delete_cf", 3, delete_cf), //delete key from cf
+        ("put_if_absent", 3, put_if_absent), // put key payload if key is absent
+        ("compare_and_swap", 4, compare_and_swap), // put key payload if current one is expected
+        ("delete_if_equals", 3, delete_if_equals), // delete key if current payload is expected
+        ("put_if_absent_cf", 4, put_if_absent_cf), // put key payload into cf if key is absent
+        ("compare_and_swap_cf", 5, compare_and_swap_cf), // put key payload into cf if current one is expected
+        ("delete_if_equals_cf", 4, delete_if_equals_cf), // delete key from cf if current payload is expected
+        ("incr", 3, incr), // add to counter, get new value
+        ("incr_cf", 4, incr_cf), // add to counter in cf, get new value
+        ("counter_get", 2, counter_get), // get counter value
+        ("estimate_num_keys", 1, estimate_num_keys), // get estimated number of keys
+        ("estimate_num_keys_cf", 2, estimate_num_keys_cf), // get estimated number of keys in cf
+        ("approximate_sizes", 3, approximate_sizes), // get approximate sizes of key ranges
+        ("approximate_sizes_cf", 4, approximate_sizes_cf), // get approximate sizes of key ranges in cf
+        ("live_files", 1, live_files), // get sst files of all cfs
+        ("column_family_metadata", 2, column_family_metadata), // get per level stats of cf
+        ("set_options", 2, set_options, SchedulerFlags::DirtyIo), // change mutable options of open db
         ("iterator_cf", 3, iterator_cf), //get cf iterator
         ("prefix_iterator_cf", 3, prefix_iterator_cf), // get prefix cf iterator
+        ("key_encode", 1, key_encode), // encode term into sortable key
+        ("key_decode", 1, key_decode), // decode sortable key into term
+        ("multi_get_slice", 3, multi_get_slice), //get payloads for keys until the timeslice is used
+        ("latest_sequence_number", 1, latest_sequence_number), // get last written seqno
+        ("get_updates_since", 2, get_updates_since), // get wal iterator from seqno
+        ("wal_next", 1, wal_next), // get next write batch from wal iterator
+        ("write_batch_data", 1, write_batch_data), // serialize wal operations
+        ("apply_batch_data", 3, apply_batch_data), // apply serialized batch on follower
+        ("applied_sequence_number", 1, applied_sequence_number), // get last applied seqno
+        ("get_async", 3, get_async), //get key payload, reply with message
+        ("put_async", 4, put_async), //put key payload, reply with message
+        ("tx_async", 3, tx_async), //atomic write batch, reply with message
+        ("multi_get_async", 3, multi_get_async), //get payloads for keys, reply with message
+        ("effective_options", 2, effective_options), // validate options, return them as map
+        ("close", 1, close, SchedulerFlags::DirtyIo), // close db and its iterators
+        ("close", 2, close, SchedulerFlags::DirtyIo), // close db with options
+        ("released", 1, released), // closed db and its files are released
+        ("cf_handle", 2, cf_handle), // get cf handle to skip lookups by name
+        ("batch_new", 0, batch_new), // create empty write batch
+        ("batch_put", 3, batch_put), // add put to batch
+        ("batch_put_cf", 4, batch_put_cf), // add put into cf to batch
+        ("batch_delete", 2, batch_delete), // add delete to batch
+        ("batch_delete_cf", 3, batch_delete_cf), // add delete from cf to batch
+        ("batch_merge", 3, batch_merge), // add merge to batch
+        ("batch_delete_range", 3, batch_delete_range), // add range delete to batch
+        ("batch_count", 1, batch_count), // get number of batch operations
+        ("batch_data_size", 1, batch_data_size), // get size of serialized batch
+        ("batch_clear", 1, batch_clear), // remove all batch operations
+        ("batch_set_savepoint", 1, batch_set_savepoint), // remember batch state
+        ("batch_rollback_to_savepoint", 1, batch_rollback_to_savepoint), // restore last saved batch state
+        ("batch_pop_savepoint", 1, batch_pop_savepoint), // forget last saved batch state
+        ("write_batch", 3, write_batch, SchedulerFlags::DirtyIo), // write batch with write options
+        ("wbwi_new", 0, wbwi_new), // create batch that can be read before writing
+        ("wbwi_get", 3, wbwi_get), // get key payload from batch over db
+        ("wbwi_iterator", 3, wbwi_iterator), // get db iterator with batch merged in
+        ("tx_set_name", 2, tx_set_name), // name batch for prepare
+        ("tx_prepare", 2, tx_prepare, SchedulerFlags::DirtyIo), // store named batch until commit or rollback
+        ("tx_rollback", 2, tx_rollback, SchedulerFlags::DirtyIo), // drop batch and its prepared record
+        ("get_all_prepared_transactions", 1, get_all_prepared_transactions, SchedulerFlags::DirtyIo), // recover prepared batches
     ],
     Some(on_load)
 );
 
-fn on_load<'a>(env: Env<'a>, _load_info: Term<'a>) -> bool {
+fn on_load<'a>(env: Env<'a>, load_info: Term<'a>) -> bool {
+
+    if let Ok(value) = load_info.map_get(atoms::async_threads().encode(env)) {
+        match value.decode::<usize>() {
+            Ok(num_threads) if num_threads > 0 => async_pool::set_threads(num_threads),
+            _ => return false,
+        }
+    }
+
+    if let Ok(value) = load_info.map_get(atoms::error_format().encode(env)) {
+        match value.atom_to_string() {
+            Ok(ref format) if format == "legacy" => errors::set_legacy(true),
+            Ok(ref format) if format == "structured" => errors::set_legacy(false),
+            _ => return false,
+        }
+    }
 
     resource!(DbResource, env);
     resource!(IteratorResource, env);
+    resource!(WalIteratorResource, env);
+    resource!(CfResource, env);
+    resource!(ValueResource, env);
+    resource!(PendingBatch, env);
+    resource!(BatchResource, env);
     true
 }
 
@@ -75,110 +512,66 @@ fn lxcode<'a>(env: Env<'a>, _args: &[Term<'a>]) -> NifResult<Term<'a>> {
 
 fn open<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let path: String = args[0].decode()?;
-    let iter: MapIterator = args[1].decode()?;
+    let decoded = match options::decode(env, options::Scope::Db, args[1])? {
+        Ok(decoded) => decoded,
+        Err(e) => return Ok(e),
+    };
+    let key_codec = decoded.key_codec;
+    let value_codec = decoded.value_codec.unwrap_or(ValueCodec::Raw);
+    let binary_threshold = decoded.binary_threshold;
+    let event_listener = decoded.event_listener;
+    let opts = decoded.opts;
+
+    let opened = if internal::exists(&path) {
+        // the options of the default cf, decoded again for its descriptor
+        let default_opts = match options::decode(env, options::Scope::Db, args[1])? {
+            Ok(decoded) => decoded.opts,
+            Err(e) => return Ok(e),
+        };
+        DB::open_cf_descriptors(&opts, path.clone(), vec![
+            ColumnFamilyDescriptor::new("default", default_opts),
+            ColumnFamilyDescriptor::new(internal::NAME, Options::default()),
+        ])
+    } else {
+        DB::open(&opts, path.clone())
+    };
+    match opened {
+        Ok(db) => {
+            let resource = DbResource::new(db, path.clone(), key_codec, value_codec, binary_threshold);
+            Ok(opened_db(env, resource, event_listener))
+        }
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
 
-    let mut opts = Options::default();
-    for (key, value) in iter {
-        let param = key.atom_to_string()?;
-        match param.as_str() {
-            "create_if_missing" => {
-                if value.atom_to_string()?.as_str() == "true" {
-                    opts.create_if_missing(true);
-                }
-            }
-            "create_missing_column_families" => {
-                if value.atom_to_string()?.as_str() == "true" {
-                    opts.create_missing_column_families(true);
-                }
-            }
-            "set_max_open_files" => {
-                let limit: i32 = value.decode()?;
-                opts.set_max_open_files(limit);
-            }
-            "set_use_fsync" => {
-                if value.atom_to_string()?.as_str() == "true" {
-                    opts.set_use_fsync(true);
-                }
-            }
-            "set_bytes_per_sync" => {
-                let limit: u64 = value.decode()?;
-                opts.set_bytes_per_sync(limit);
-            }
-            "optimize_for_point_lookup" => {
-                let limit: u64 = value.decode()?;
-                opts.optimize_for_point_lookup(limit);
-            }
-            "set_table_cache_num_shard_bits" => {
-                let limit: i32 = value.decode()?;
-                opts.set_table_cache_num_shard_bits(limit);
-            }
-            "set_max_write_buffer_number" => {
-                let limit: i32 = value.decode()?;
-                opts.set_max_write_buffer_number(limit);
-            }
-            "set_write_buffer_size" => {
-                let limit: usize = value.decode()?;
-                opts.set_write_buffer_size(limit);
-            }
-            "set_target_file_size_base" => {
-                let limit: u64 = value.decode()?;
-                opts.set_target_file_size_base(limit);
-            }
-            "set_min_write_buffer_number_to_merge" => {
-                let limit: i32 = value.decode()?;
-                opts.set_min_write_buffer_number_to_merge(limit);
-            }
-            "set_level_zero_stop_writes_trigger" => {
-                let limit: i32 = value.decode()?;
-                opts.set_level_zero_stop_writes_trigger(limit);
+// {ok, Db} once the id of the internal cf is known
+fn opened_db<'a>(env: Env<'a>, resource: DbResource, listener: Option<LocalPid>) -> Term<'a> {
+    let id = match resource.read_db() {
+        Some(db) => internal::id(&db),
+        None => Ok(None),
+    };
+    match id {
+        Ok(id) => {
+            *resource.internal_cf.write_safe() = id;
+            let resource = ResourceArc::new(resource);
+            if let Some(listener) = listener {
+                watch_events(resource.clone(), listener);
             }
-            "set_level_zero_slowdown_writes_trigger" => {
-                let limit: i32 = value.decode()?;
-                opts.set_level_zero_slowdown_writes_trigger(limit);
-            }
-            "set_max_background_compactions" => {
-                let limit: i32 = value.decode()?;
-                opts.set_max_background_compactions(limit);
-            }
-            "set_max_background_flushes" => {
-                let limit: i32 = value.decode()?;
-                opts.set_max_background_flushes(limit);
-            }
-            "set_disable_auto_compactions" => {
-                if value.atom_to_string()?.as_str() == "true" {
-                    opts.set_disable_auto_compactions(true);
-                }
-            }
-            "set_compaction_style" => {
-                let style = value.atom_to_string()?;
-                if style == "level" {
-                    opts.set_compaction_style(DBCompactionStyle::Level);
-                } else if style == "universal" {
-                    opts.set_compaction_style(DBCompactionStyle::Universal);
-                } else if style == "fifo" {
-                    opts.set_compaction_style(DBCompactionStyle::Fifo);
-                }
-            }
-            "prefix_length" => {
-                let limit: usize = value.decode()?;
-                let prefix_extractor = rocksdb::SliceTransform::create_fixed_prefix(limit);
-                opts.set_prefix_extractor(prefix_extractor);
-            }
-            _ => {}
+            (atoms::ok(), resource).encode(env)
         }
+        Err(e) => errors::status(env, e),
     }
+}
 
-    match DB::open(&opts, path.clone()) {
-        Ok(db) => {
-            let resource = ResourceArc::new(DbResource {
-                db: RwLock::new(
-                    db
-                ),
-                path: path.clone(),
-            });
-            Ok((atoms::ok(), resource.encode(env)).encode(env))
-        }
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+
+fn effective_options<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let scope = match options::Scope::from_atom(&args[0].atom_to_string()?) {
+        Some(scope) => scope,
+        None => return Err(rustler::Error::BadArg),
+    };
+    match options::decode(env, scope, args[1])? {
+        Ok(decoded) => Ok((atoms::ok(), decoded.effective(env, scope)?).encode(env)),
+        Err(e) => Ok(e),
     }
 }
 
@@ -186,17 +579,16 @@ fn open<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
 fn open_default<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let path: String = args[0].decode()?;
 
-    match DB::open_default(path.clone()) {
-        Ok(db) => {
-            let resource = ResourceArc::new(DbResource {
-                db: RwLock::new(
-                    db
-                ),
-                path: path.clone(),
-            });
-            Ok((atoms::ok(), resource.encode(env)).encode(env))
-        }
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+    let opened = if internal::exists(&path) {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        DB::open_cf(&opts, path.clone(), &["default", internal::NAME])
+    } else {
+        DB::open_default(path.clone())
+    };
+    match opened {
+        Ok(db) => Ok(opened_db(env, DbResource::new(db, path.clone(), KeyCodec::Raw, ValueCodec::Raw, usize::MAX), None)),
+        Err(e) => Ok(errors::db_error(env, &e)),
     }
 }
 
@@ -209,15 +601,99 @@ fn open_cf_default<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
         let name: String = elem.decode()?;
         cfs.push(name);
     }
+    if internal::exists(&path) && !cfs.iter().any(|cf| cf == internal::NAME) {
+        cfs.push(internal::NAME.to_string());
+    }
     let cfs2: Vec<&str> = cfs.iter().map(|s| &**s).collect();
-    let resource = ResourceArc::new(DbResource {
-        db: RwLock::new(
-            DB::open_cf(&Options::default(), path.clone(), &cfs2).unwrap()
-        ),
-        path: path.clone(),
-    });
+    match DB::open_cf(&Options::default(), path.clone(), &cfs2) {
+        Ok(db) => Ok(opened_db(env, DbResource::new(db, path.clone(), KeyCodec::Raw, ValueCodec::Raw, usize::MAX), None)),
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
 
-    Ok((atoms::ok(), resource.encode(env)).encode(env))
+
+// Options are not stored in the db, a db with cfs is reopened with the
+// options of every cf, e.g. their value codecs.
+fn open_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let path: String = args[0].decode()?;
+    let decoded = match options::decode(env, options::Scope::Db, args[1])? {
+        Ok(decoded) => decoded,
+        Err(e) => return Ok(e),
+    };
+    let iter: MapIterator = args[2].decode()?;
+    let mut descriptors = Vec::new();
+    let mut cf_value_codecs = HashMap::new();
+    for (name, cf_opts) in iter {
+        let name: String = name.decode()?;
+        if name == internal::NAME {
+            continue;
+        }
+        let cf_decoded = match options::decode(env, options::Scope::Cf, cf_opts)? {
+            Ok(cf_decoded) => cf_decoded,
+            Err(e) => return Ok(e),
+        };
+        if let Some(codec) = cf_decoded.value_codec {
+            cf_value_codecs.insert(name.clone(), codec);
+        }
+        descriptors.push(ColumnFamilyDescriptor::new(name, cf_decoded.opts));
+    }
+    if internal::exists(&path) {
+        descriptors.push(ColumnFamilyDescriptor::new(internal::NAME, Options::default()));
+    }
+    let value_codec = decoded.value_codec.unwrap_or(ValueCodec::Raw);
+    match DB::open_cf_descriptors(&decoded.opts, path.clone(), descriptors) {
+        Ok(db) => {
+            let resource = DbResource::new(db, path, decoded.key_codec, value_codec, decoded.binary_threshold);
+            *resource.cf_value_codecs.write_safe() = cf_value_codecs;
+            Ok(opened_db(env, resource, decoded.event_listener))
+        }
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
+
+
+fn close<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let mut flush = false;
+    if args.len() > 1 {
+        let iter: MapIterator = args[1].decode()?;
+        for (key, value) in iter {
+            match key.atom_to_string()?.as_str() {
+                "flush" => match value.decode() {
+                    Ok(value) => flush = value,
+                    Err(_) => return Ok(errors::error(env, (atoms::invalid_option(), key, value))),
+                },
+                _ => return Ok(errors::error(env, (atoms::unknown_option(), key))),
+            }
+        }
+    }
+
+    // waits for running operations, later ones see the db closed
+    let mut db = resource.db.write_safe();
+    if db.is_none() {
+        return Ok((atoms::ok()).encode(env));
+    }
+    if flush {
+        if let Err(e) = db.as_ref().expect("checked above").flush() {
+            return Ok(errors::db_error(env, &e));
+        }
+    }
+    invalidate(&resource.iterators, None);
+    invalidate(&resource.wal_iterators, None);
+    invalidate(&resource.cf_handles, None);
+    let mut closing = db.take();
+    let mut pins = lock_safe(&resource.pins);
+    if pins.count > 0 {
+        pins.retired = closing.take();
+    }
+    Ok((atoms::ok()).encode(env))
+}
+
+// Waits for a retired db being closed by the last unpin
+fn released<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let open = resource.db.read_safe().is_some();
+    Ok((!open && lock_safe(&resource.pins).retired.is_none()).encode(env))
 }
 
 
@@ -225,7 +701,7 @@ fn destroy<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let path: String = args[0].decode()?;
     match DB::destroy(&Options::default(), path) {
         Ok(_) => Ok((atoms::ok()).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+        Err(e) => Ok(errors::db_error(env, &e)),
     }
 }
 
@@ -234,7 +710,7 @@ fn repair<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let path: String = args[0].decode()?;
     match DB::repair(Options::default(), path) {
         Ok(_) => Ok((atoms::ok()).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+        Err(e) => Ok(errors::db_error(env, &e)),
     }
 }
 
@@ -248,87 +724,250 @@ fn path<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
 
 fn put<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
-    let key: Binary = args[1].decode()?;
-    let value: Binary = args[2].decode()?;
-    let db = resource.db.write().unwrap();
-    match db.put(&key, &value) {
-        Ok(_) => Ok((atoms::ok()).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+    let key = resource.key_codec.encode(args[1])?;
+    let value = resource.value_codec.encode(args[2])?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    Ok(put_value(env, &db, &key, &value))
+}
+
+fn put_value<'a>(env: Env<'a>, db: &DB, key: &[u8], value: &[u8]) -> Term<'a> {
+    match db.put(key, value) {
+        Ok(_) => (atoms::ok()).encode(env),
+        Err(e) => errors::db_error(env, &e),
     }
 }
 
 
 fn get<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
-    let key: Binary = args[1].decode()?;
-    let db = resource.db.read().unwrap();
-    match db.get(&key) {
-        Ok(Some(v)) => {
-            let mut value = OwnedBinary::new(v[..].len()).unwrap();
-            value.clone_from_slice(&v[..]);
-            Ok((atoms::ok(), value.release(env) ).encode(env))
-        }
-        Ok(None) => Ok((atoms::notfound()).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+    let key = resource.key_codec.encode(args[1])?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    Ok(get_value(env, &resource, &db, resource.value_codec, &key))
+}
+
+fn get_value<'a>(env: Env<'a>, resource: &ResourceArc<DbResource>, db: &DB, codec: ValueCodec, key: &[u8]) -> Term<'a> {
+    match db.get_pinned(key) {
+        Ok(Some(v)) => pinned_value(env, resource, codec, v),
+        Ok(None) => (atoms::notfound()).encode(env),
+        Err(e) => errors::db_error(env, &e),
+    }
+}
+
+// {ok, Value}, large raw values keep the slice instead of copying it
+fn pinned_value<'a>(env: Env<'a>, resource: &ResourceArc<DbResource>, codec: ValueCodec, v: DBPinnableSlice) -> Term<'a> {
+    if codec == ValueCodec::Raw && v.len() >= resource.binary_threshold {
+        resource.pin();
+        // outlives the borrow of the db, the pin keeps it open
+        let v: DBPinnableSlice<'static> = unsafe { mem::transmute(v) };
+        let value = ValueResource {
+            bytes: ValueBytes::Pinned(v),
+            _pin: Some(Pin(resource.clone())),
+        };
+        return (atoms::ok(), resource_binary(env, value)).encode(env);
     }
+    match codec.decode(env, &v) {
+        Ok(value) => (atoms::ok(), value).encode(env),
+        Err(e) => errors::corruption(env, e),
+    }
+}
+
+fn resource_binary<'a>(env: Env<'a>, value: ValueResource) -> Term<'a> {
+    let value = ResourceArc::new(value);
+    value.make_binary(env, |value| value.bytes.as_slice()).encode(env)
 }
 
 
 fn delete<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
-    let key: Binary = args[1].decode()?;
-    let db = resource.db.write().unwrap();
+    let key = resource.key_codec.encode(args[1])?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
     match db.delete(&key) {
         Ok(_) => Ok((atoms::ok()).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+        Err(e) => Ok(errors::db_error(env, &e)),
     }
 }
 
 
-fn tx<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+// tx/2 calls it again with {continue, Pending, Rest} until the batch
+// is complete, it's written at once so the tx stays atomic.
+fn tx_slice<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
-    let iter: ListIterator = args[1].decode()?;
-    let db = resource.db.write().unwrap();
-    let mut batch = WriteBatch::default();
-    for elem in iter {
-        let terms: Vec<Term> = ::rustler::types::tuple::get_tuple(elem)?;
-        if terms.len() >= 2 {
-            let op: String = terms[0].atom_to_string()?;
-            match op.as_str() {
-                "put" => {
-                    let key: Binary = terms[1].decode()?;
-                    let val: Binary = terms[2].decode()?;
-                    let _ = batch.put(&key, &val);
-                }
-                "put_cf" => {
-                    let cf: String = terms[1].decode()?;
-                    let key: Binary = terms[2].decode()?;
-                    let value: Binary = terms[3].decode()?;
-                    let cf_handler = db.cf_handle(&cf.as_str()).unwrap();
-                    let _ = batch.put_cf(cf_handler, &key, &value);
-                }
-                "delete" => {
-                    let key: Binary = terms[1].decode()?;
-                    let _ = batch.delete(&key);
-                }
-                "delete_cf" => {
-                    let cf: String = terms[1].decode()?;
-                    let key: Binary = terms[2].decode()?;
-                    let cf_handler = db.cf_handle(&cf.as_str()).unwrap();
-                    let _ = batch.delete_cf(cf_handler, &key);
-                }
-                _ => {}
+    // undefined on the first call
+    let pending: Option<ResourceArc<PendingBatch>> = if args[2].is_atom() {
+        None
+    } else {
+        Some(args[2].decode()?)
+    };
+    let mut builder = match pending {
+        Some(ref pending) => lock_safe(&pending.builder).take().ok_or(rustler::Error::BadArg)?,
+        None => TxBuilder::default(),
+    };
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let mut timeslice = Timeslice::new(env);
+    match add_ops(&db, &resource, &mut builder, args[1], Some(&mut timeslice))? {
+        Ok(None) => Ok(write_tx(env, &db, &resource, builder)),
+        Ok(Some(rest)) => {
+            let pending = match pending {
+                Some(pending) => pending,
+                None => ResourceArc::new(PendingBatch { builder: Mutex::new(None) }),
+            };
+            *lock_safe(&pending.builder) = Some(builder);
+            Ok((atoms::continue_(), pending, rest).encode(env))
+        }
+        Err(e) => Ok(tx_error(env, e)),
+    }
+}
+
+fn build_batch<'a>(db: &DB, resource: &ResourceArc<DbResource>, list: Term<'a>) -> NifResult<Result<TxBuilder, TxError<'a>>> {
+    let mut builder = TxBuilder::default();
+    Ok(add_ops(db, resource, &mut builder, list, None)?.map(|_| builder))
+}
+
+// The batch of a tx/2 and the number of ops added to it
+#[derive(Default)]
+struct TxBuilder {
+    batch: WriteBatch,
+    ops: usize,
+    // incr ops, added to the batch when it's written
+    counters: Vec<Counter>,
+}
+
+// The cf is looked up again by name when the batch is written
+struct Counter {
+    cf: Option<String>,
+    key: Vec<u8>,
+    delta: i64,
+}
+
+// Index is the position of the op in the list, starting at 1
+enum TxError<'a> {
+    BadOp(usize, Term<'a>),
+    Cf(usize, CfError),
+    Db(rocksdb::Error),
+}
+
+fn tx_error<'a>(env: Env<'a>, e: TxError<'a>) -> Term<'a> {
+    match e {
+        TxError::BadOp(index, op) => errors::error(env, (atoms::bad_op(), index, op)),
+        TxError::Cf(index, CfError::Unknown(name)) => errors::error(env, (atoms::unknown_cf(), index, name)),
+        TxError::Cf(index, CfError::Dropped) => errors::error(env, (atoms::cf_dropped(), index)),
+        TxError::Db(e) => errors::db_error(env, &e),
+    }
+}
+
+// Some(Rest) when the timeslice was used before the end of the list
+fn add_ops<'a>(db: &DB, resource: &ResourceArc<DbResource>, builder: &mut TxBuilder, list: Term<'a>,
+               mut timeslice: Option<&mut Timeslice>) -> NifResult<Result<Option<Term<'a>>, TxError<'a>>> {
+    let mut list = list;
+    while !list.is_empty_list() {
+        if let Some(ref mut timeslice) = timeslice {
+            if timeslice.tick() {
+                return Ok(Ok(Some(list)));
             }
         }
+        let (op, tail) = list.list_get_cell()?;
+        list = tail;
+        builder.ops += 1;
+        let index = builder.ops;
+        if let Err(e) = add_op(db, resource, builder, index, op) {
+            return Ok(Err(e));
+        }
+    }
+    Ok(Ok(None))
+}
+
+// Anything but a known op of the right arity, with a key and value
+// the codecs accept, is a bad_op.
+fn add_op<'a>(db: &DB, resource: &ResourceArc<DbResource>, builder: &mut TxBuilder, index: usize, op: Term<'a>) -> Result<(), TxError<'a>> {
+    let batch = &mut builder.batch;
+    let bad_op = |_| TxError::BadOp(index, op);
+    let lookup = |term| match lookup_cf(db, resource, term) {
+        Ok(Ok(cf)) => Ok(cf),
+        Ok(Err(e)) => Err(TxError::Cf(index, e)),
+        Err(_) => Err(TxError::BadOp(index, op)),
+    };
+    let terms: Vec<Term> = ::rustler::types::tuple::get_tuple(op).map_err(bad_op)?;
+    let name = match terms.first() {
+        Some(name) => name.atom_to_string().map_err(bad_op)?,
+        None => return Err(TxError::BadOp(index, op)),
+    };
+    let result = match (name.as_str(), terms.len()) {
+        ("put", 3) => {
+            let key = resource.key_codec.encode(terms[1]).map_err(bad_op)?;
+            let value = resource.value_codec.encode(terms[2]).map_err(bad_op)?;
+            batch.put(&key, &value)
+        }
+        ("put_cf", 4) => {
+            let cf = lookup(terms[1])?;
+            let key = resource.key_codec.encode(terms[2]).map_err(bad_op)?;
+            let value = cf.value_codec.encode(terms[3]).map_err(bad_op)?;
+            batch.put_cf(cf.handle, &key, &value)
+        }
+        ("delete", 2) => {
+            let key = resource.key_codec.encode(terms[1]).map_err(bad_op)?;
+            batch.delete(&key)
+        }
+        ("delete_cf", 3) => {
+            let cf = lookup(terms[1])?;
+            let key = resource.key_codec.encode(terms[2]).map_err(bad_op)?;
+            batch.delete_cf(cf.handle, &key)
+        }
+        ("incr", 3) => {
+            let key = resource.key_codec.encode(terms[1]).map_err(bad_op)?;
+            let delta: i64 = terms[2].decode().map_err(bad_op)?;
+            builder.counters.push(Counter { cf: None, key: key.into_owned(), delta });
+            Ok(())
+        }
+        ("incr", 4) => {
+            let cf = lookup(terms[1])?;
+            let key = resource.key_codec.encode(terms[2]).map_err(bad_op)?;
+            let delta: i64 = terms[3].decode().map_err(bad_op)?;
+            builder.counters.push(Counter { cf: Some(cf.name), key: key.into_owned(), delta });
+            Ok(())
+        }
+        _ => return Err(TxError::BadOp(index, op)),
+    };
+    result.map_err(TxError::Db)
+}
+
+fn closed<'a>(env: Env<'a>) -> Term<'a> {
+    errors::error(env, atoms::closed())
+}
+
+fn unknown_cf<'a>(env: Env<'a>, name: &str) -> Term<'a> {
+    errors::error(env, (atoms::unknown_cf(), name))
+}
+
+fn write_tx<'a>(env: Env<'a>, db: &DB, resource: &DbResource, builder: TxBuilder) -> Term<'a> {
+    let mut batch = builder.batch;
+    // counters are read under their key locks, held until the write
+    let stripes = builder.counters.iter()
+        .map(|c| resource.key_locks.stripe(c.cf.as_ref().map_or("default", |cf| cf), &c.key))
+        .collect();
+    let _key_locks = resource.key_locks.lock_all(stripes);
+    if let Err(e) = add_counters(db, &mut batch, builder.counters) {
+        return counter_error(env, e);
     }
     if batch.len() > 0 {
         let applied = batch.len();
         match db.write(batch) {
-            Ok(_) => Ok((atoms::ok(), applied).encode(env)),
-            Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+            Ok(_) => (atoms::ok(), applied).encode(env),
+            Err(e) => errors::db_error(env, &e),
         }
     } else {
-        Ok((atoms::ok(), 0).encode(env))
+        (atoms::ok(), 0).encode(env)
     }
 }
 
@@ -336,14 +975,17 @@ fn tx<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
 fn iterator<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
     let mode_terms: Vec<Term> = ::rustler::types::tuple::get_tuple(args[1])?;
-    let db = resource.db.read().unwrap();
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
     let mut iterator = db.iterator(IteratorMode::Start);
     if mode_terms.len() >= 1 {
         let mode: String = mode_terms[0].atom_to_string()?;
         match mode.as_str() {
             "end" => iterator = db.iterator(IteratorMode::End),
             "from" => {
-                let from: Binary = mode_terms[1].decode()?;
+                let from = resource.key_codec.encode(mode_terms[1])?;
                 if mode_terms.len() == 3 {
                     let direction: String = mode_terms[2].atom_to_string()?;
                     iterator = match direction.as_str() {
@@ -359,9 +1001,12 @@ fn iterator<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     }
 
     let resource = ResourceArc::new(IteratorResource {
-        iter: RwLock::new(
-            iterator,
-        ),
+        iter: resource.track_iterator(iterator, None),
+        db: resource.clone(),
+        cf: None,
+        key_codec: resource.key_codec,
+        value_codec: resource.value_codec,
+        overlay: None,
     });
 
     Ok((atoms::ok(), resource.encode(env)).encode(env))
@@ -372,13 +1017,19 @@ fn prefix_iterator<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
     let prefix: Binary = args[1].decode()?;
 
-    let db = resource.db.read().unwrap();
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
     let iterator = db.prefix_iterator(&prefix);
 
     let resource = ResourceArc::new(IteratorResource {
-        iter: RwLock::new(
-            iterator,
-        ),
+        iter: resource.track_iterator(iterator, None),
+        db: resource.clone(),
+        cf: None,
+        key_codec: resource.key_codec,
+        value_codec: resource.value_codec,
+        overlay: None,
     });
 
     Ok((atoms::ok(), resource.encode(env)).encode(env))
@@ -387,24 +1038,46 @@ fn prefix_iterator<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
 
 fn iterator_valid<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<IteratorResource> = args[0].decode()?;
-    let iter = resource.iter.read().unwrap();
-    Ok((atoms::ok(), iter.valid()).encode(env))
+    let iter = resource.iter.read_safe();
+    let valid = match *iter {
+        Some(ref iter) => iter.valid(),
+        None => return Ok(resource.invalidated(env)),
+    };
+    let pending = match resource.overlay {
+        Some(ref overlay) => lock_safe(overlay).pending(),
+        None => false,
+    };
+    Ok((atoms::ok(), valid || pending).encode(env))
 }
 
 
 fn next<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<IteratorResource> = args[0].decode()?;
-    let mut iter = resource.iter.write().unwrap();
-    match iter.next() {
+    let mut iter = resource.iter.write_safe();
+    let iter = match *iter {
+        Some(ref mut iter) => iter,
+        None => return Ok(resource.invalidated(env)),
+    };
+    let next = match resource.overlay {
+        Some(ref overlay) => lock_safe(overlay).next(iter),
+        None => iter.next(),
+    };
+    match next {
         None => Ok((atoms::ok()).encode(env)),
         Some((k, v)) => {
-            let mut key = OwnedBinary::new(k[..].len()).unwrap();
-            key.clone_from_slice(&k[..]);
-
-            let mut value = OwnedBinary::new(v[..].len()).unwrap();
-            value.clone_from_slice(&v[..]);
+            let key = resource.key_codec.decode(env, &k[..])?;
+            if resource.value_codec == ValueCodec::Raw && v.len() >= resource.db.binary_threshold {
+                let value = ValueResource {
+                    bytes: ValueBytes::Boxed(v),
+                    _pin: None,
+                };
+                return Ok((atoms::ok(), key, resource_binary(env, value)).encode(env));
+            }
 
-            Ok((atoms::ok(), key.release(env), value.release(env) ).encode(env))
+            match resource.value_codec.decode(env, &v[..]) {
+                Ok(value) => Ok((atoms::ok(), key, value).encode(env)),
+                Err(e) => Ok(errors::corruption(env, e)),
+            }
         }
     }
 }
@@ -414,12 +1087,18 @@ fn create_cf_default<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>>
     let resource: ResourceArc<DbResource> = args[0].decode()?;
     let name: String = args[1].decode()?;
 
-    let mut db = resource.db.write().unwrap();
+    let mut db = match resource.write_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    if name == internal::NAME {
+        return Ok(reserved_cf(env));
+    }
     let opts = Options::default();
 
     match db.create_cf(name.as_str(), &opts) {
         Ok(_) => Ok((atoms::ok()).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+        Err(e) => Ok(errors::db_error(env, &e)),
     }
 }
 
@@ -427,104 +1106,32 @@ fn create_cf_default<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>>
 fn create_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
     let name: String = args[1].decode()?;
-    let iter: MapIterator = args[2].decode()?;
-
-    let mut db = resource.db.write().unwrap();
+    let decoded = match options::decode(env, options::Scope::Cf, args[2])? {
+        Ok(decoded) => decoded,
+        Err(e) => return Ok(e),
+    };
+    let value_codec = decoded.value_codec;
+    let opts = decoded.opts;
+
+    let mut db = match resource.write_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    if name == internal::NAME {
+        return Ok(reserved_cf(env));
+    }
 
-    let mut opts = Options::default();
-    for (key, value) in iter {
-        let param = key.atom_to_string()?;
-        match param.as_str() {
-            "create_if_missing" => {
-                if value.atom_to_string()?.as_str() == "true" {
-                    opts.create_if_missing(true);
-                }
-            }
-            "create_missing_column_families" => {
-                if value.atom_to_string()?.as_str() == "true" {
-                    opts.create_missing_column_families(true);
-                }
-            }
-            "set_max_open_files" => {
-                let limit: i32 = value.decode()?;
-                opts.set_max_open_files(limit);
-            }
-            "set_use_fsync" => {
-                if value.atom_to_string()?.as_str() == "true" {
-                    opts.set_use_fsync(true);
-                }
-            }
-            "set_bytes_per_sync" => {
-                let limit: u64 = value.decode()?;
-                opts.set_bytes_per_sync(limit);
-            }
-            "optimize_for_point_lookup" => {
-                let limit: u64 = value.decode()?;
-                opts.optimize_for_point_lookup(limit);
-            }
-            "set_table_cache_num_shard_bits" => {
-                let limit: i32 = value.decode()?;
-                opts.set_table_cache_num_shard_bits(limit);
-            }
-            "set_max_write_buffer_number" => {
-                let limit: i32 = value.decode()?;
-                opts.set_max_write_buffer_number(limit);
-            }
-            "set_write_buffer_size" => {
-                let limit: usize = value.decode()?;
-                opts.set_write_buffer_size(limit);
-            }
-            "set_target_file_size_base" => {
-                let limit: u64 = value.decode()?;
-                opts.set_target_file_size_base(limit);
-            }
-            "set_min_write_buffer_number_to_merge" => {
-                let limit: i32 = value.decode()?;
-                opts.set_min_write_buffer_number_to_merge(limit);
-            }
-            "set_level_zero_stop_writes_trigger" => {
-                let limit: i32 = value.decode()?;
-                opts.set_level_zero_stop_writes_trigger(limit);
-            }
-            "set_level_zero_slowdown_writes_trigger" => {
-                let limit: i32 = value.decode()?;
-                opts.set_level_zero_slowdown_writes_trigger(limit);
-            }
-            "set_max_background_compactions" => {
-                let limit: i32 = value.decode()?;
-                opts.set_max_background_compactions(limit);
-            }
-            "set_max_background_flushes" => {
-                let limit: i32 = value.decode()?;
-                opts.set_max_background_flushes(limit);
-            }
-            "set_disable_auto_compactions" => {
-                if value.atom_to_string()?.as_str() == "true" {
-                    opts.set_disable_auto_compactions(true);
-                }
-            }
-            "set_compaction_style" => {
-                let style = value.atom_to_string()?;
-                if style == "level" {
-                    opts.set_compaction_style(DBCompactionStyle::Level);
-                } else if style == "universal" {
-                    opts.set_compaction_style(DBCompactionStyle::Universal);
-                } else if style == "fifo" {
-                    opts.set_compaction_style(DBCompactionStyle::Fifo);
-                }
+    match db.create_cf(name.as_str(), &opts) {
+        Ok(_) => {
+            if let Some(ref mut watched) = *lock_safe(&resource.watched) {
+                watched.add_cf(&name);
             }
-            "prefix_length" => {
-                let limit: usize = value.decode()?;
-                let prefix_extractor = rocksdb::SliceTransform::create_fixed_prefix(limit);
-                opts.set_prefix_extractor(prefix_extractor);
+            if let Some(codec) = value_codec {
+                resource.cf_value_codecs.write_safe().insert(name, codec);
             }
-            _ => {}
+            Ok((atoms::ok()).encode(env))
         }
-    }
-
-    match db.create_cf(name.as_str(), &opts) {
-        Ok(_) => Ok((atoms::ok()).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+        Err(e) => Ok(errors::db_error(env, &e)),
     }
 }
 
@@ -532,80 +1139,683 @@ fn create_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
 fn list_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let path: String = args[0].decode()?;
     match DB::list_cf(&Options::default(), path) {
-        Ok(cfs) => Ok((atoms::ok(), cfs).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+        Ok(mut cfs) => {
+            cfs.retain(|cf| cf != internal::NAME);
+            Ok((atoms::ok(), cfs).encode(env))
+        }
+        Err(e) => Ok(errors::db_error(env, &e)),
     }
 }
 
 
 fn drop_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
-    let name: String = args[1].decode()?;
-
-    let mut db = resource.db.write().unwrap();
+    let handle = args[1].decode::<ResourceArc<CfResource>>().ok();
+    let name: String = match handle {
+        Some(ref cf) => {
+            if !ptr::eq(&*cf.db, &*resource) {
+                return Err(rustler::Error::BadArg);
+            }
+            cf.name.clone()
+        }
+        None => args[1].decode()?,
+    };
+
+    let mut db = match resource.write_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    // checked under the write lock, a drop in between invalidates it
+    if let Some(ref cf) = handle {
+        if cf.handle.read_safe().is_none() {
+            return Ok(errors::error(env, atoms::cf_dropped()));
+        }
+    }
+    if user_cf_handle(&db, &name).is_none() {
+        return Ok(unknown_cf(env, &name));
+    }
+    invalidate(&resource.iterators, Some(&name));
+    invalidate(&resource.cf_handles, Some(&name));
 
     match db.drop_cf(name.as_str()) {
-        Ok(_) => Ok((atoms::ok()).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+        Ok(_) => {
+            resource.cf_value_codecs.write_safe().remove(&name);
+            if let Some(ref mut watched) = *lock_safe(&resource.watched) {
+                watched.remove_cf(&name);
+            }
+            Ok((atoms::ok()).encode(env))
+        }
+        Err(e) => Ok(errors::db_error(env, &e)),
     }
 }
 
 
+fn cf_handle<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let name: String = args[1].decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let handle = match user_cf_handle(&db, &name) {
+        Some(handle) => handle,
+        None => return Ok(unknown_cf(env, &name)),
+    };
+    let cf = ResourceArc::new(CfResource {
+        handle: resource.track_cf_handle(CfHandle(handle), &name),
+        db: resource.clone(),
+        value_codec: resource.cf_value_codec(&name),
+        name,
+    });
+    Ok((atoms::ok(), cf.encode(env)).encode(env))
+}
+
 fn put_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
-    let cf: String = args[1].decode()?;
-    let key: Binary = args[2].decode()?;
-    let value: Binary = args[3].decode()?;
-    let db = resource.db.write().unwrap();
-    let cf_handler = db.cf_handle(&cf.as_str()).unwrap();
-    match db.put_cf(cf_handler, &key, &value) {
+    let key = resource.key_codec.encode(args[2])?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let cf = match lookup_cf(&db, &resource, args[1])? {
+        Ok(cf) => cf,
+        Err(e) => return Ok(cf_error(env, e)),
+    };
+    let value = cf.value_codec.encode(args[3])?;
+    match db.put_cf(cf.handle, &key, &value) {
         Ok(_) => Ok((atoms::ok()).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+        Err(e) => Ok(errors::db_error(env, &e)),
     }
 }
 
 fn get_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
-    let cf: String = args[1].decode()?;
-    let key: Binary = args[2].decode()?;
-    let db = resource.db.read().unwrap();
-    let cf_handler = db.cf_handle(&cf.as_str()).unwrap();
-    match db.get_cf(cf_handler, &key) {
-        Ok(Some(v)) => {
-            let mut value = OwnedBinary::new(v[..].len()).unwrap();
-            value.clone_from_slice(&v[..]);
-            Ok((atoms::ok(), value.release(env)).encode(env))
-        }
-        Ok(None) => Ok((atoms::notfound()).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
-    }
+    let key = resource.key_codec.encode(args[2])?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let cf = match lookup_cf(&db, &resource, args[1])? {
+        Ok(cf) => cf,
+        Err(e) => return Ok(cf_error(env, e)),
+    };
+    let value = match db.get_pinned_cf(cf.handle, &key) {
+        Ok(Some(v)) => pinned_value(env, &resource, cf.value_codec, v),
+        Ok(None) => (atoms::notfound()).encode(env),
+        Err(e) => errors::db_error(env, &e),
+    };
+    Ok(value)
 }
 
 fn delete_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
-    let cf: String = args[1].decode()?;
-    let key: Binary = args[2].decode()?;
-    let db = resource.db.read().unwrap();
-    let cf_handler = db.cf_handle(&cf.as_str()).unwrap();
-    match db.delete_cf(cf_handler, &key) {
+    let key = resource.key_codec.encode(args[2])?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let cf = match lookup_cf(&db, &resource, args[1])? {
+        Ok(cf) => cf,
+        Err(e) => return Ok(cf_error(env, e)),
+    };
+    match db.delete_cf(cf.handle, &key) {
+        Ok(_) => Ok((atoms::ok()).encode(env)),
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
+
+fn put_if_absent<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    conditional_write(env, args[0], None, args[1], None, Some(args[2]))
+}
+
+fn compare_and_swap<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    conditional_write(env, args[0], None, args[1], expected(args[2])?, Some(args[3]))
+}
+
+fn delete_if_equals<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    conditional_write(env, args[0], None, args[1], expected(args[2])?, None)
+}
+
+fn put_if_absent_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    conditional_write(env, args[0], Some(args[1]), args[2], None, Some(args[3]))
+}
+
+fn compare_and_swap_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    conditional_write(env, args[0], Some(args[1]), args[2], expected(args[3])?, Some(args[4]))
+}
+
+fn delete_if_equals_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    conditional_write(env, args[0], Some(args[1]), args[2], expected(args[3])?, None)
+}
+
+// notfound expects the key to be absent
+fn expected(term: Term) -> NifResult<Option<Term>> {
+    if term.is_atom() && term.atom_to_string()? == "notfound" {
+        return Ok(None);
+    }
+    Ok(Some(term))
+}
+
+// Writes `new`, or deletes the key when it's None, if the current value is
+// `expected`. Values are compared as terms after decoding.
+fn conditional_write<'a>(env: Env<'a>, db_term: Term<'a>, cf_term: Option<Term<'a>>, key: Term<'a>,
+                         expected: Option<Term<'a>>, new: Option<Term<'a>>) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = db_term.decode()?;
+    let key = resource.key_codec.encode(key)?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let cf = match cf_term {
+        Some(term) => match lookup_cf(&db, &resource, term)? {
+            Ok(cf) => Some(cf),
+            Err(e) => return Ok(cf_error(env, e)),
+        },
+        None => None,
+    };
+    let codec = match cf {
+        Some(ref cf) => cf.value_codec,
+        None => resource.value_codec,
+    };
+    let new = match new {
+        Some(term) => Some(codec.encode(term)?),
+        None => None,
+    };
+    let _key_lock = resource.key_locks.lock(cf.as_ref().map_or("default", |cf| &cf.name), &key);
+    let read = match cf {
+        Some(ref cf) => db.get_pinned_cf(cf.handle, &key),
+        None => db.get_pinned(&key),
+    };
+    let current = match read {
+        Ok(Some(v)) => match codec.decode(env, &v) {
+            Ok(value) => Some(value),
+            Err(e) => return Ok(errors::corruption(env, e)),
+        },
+        Ok(None) => None,
+        Err(e) => return Ok(errors::db_error(env, &e)),
+    };
+    let matches = match (expected, current) {
+        (None, None) => true,
+        (Some(expected), Some(current)) => expected == current,
+        _ => false,
+    };
+    if !matches {
+        let current = match current {
+            Some(value) => value,
+            None => (atoms::notfound()).encode(env),
+        };
+        return Ok(errors::error(env, (atoms::mismatch(), current)));
+    }
+    let result = match (cf, new) {
+        (Some(cf), Some(value)) => db.put_cf(cf.handle, &key, &value),
+        (Some(cf), None) => db.delete_cf(cf.handle, &key),
+        (None, Some(value)) => db.put(&key, &value),
+        (None, None) => db.delete(&key),
+    };
+    match result {
+        Ok(_) => Ok((atoms::ok()).encode(env)),
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
+
+// Counters are 8 byte big-endian signed integers, whatever the value
+// codec. An absent counter is 0.
+fn incr<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    incr_counter(env, args[0], None, args[1], args[2])
+}
+
+fn incr_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    incr_counter(env, args[0], Some(args[1]), args[2], args[3])
+}
+
+fn incr_counter<'a>(env: Env<'a>, db_term: Term<'a>, cf_term: Option<Term<'a>>, key: Term<'a>, delta: Term<'a>) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = db_term.decode()?;
+    let key = resource.key_codec.encode(key)?;
+    let delta: i64 = delta.decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let cf = match cf_term {
+        Some(term) => match lookup_cf(&db, &resource, term)? {
+            Ok(cf) => Some(cf),
+            Err(e) => return Ok(cf_error(env, e)),
+        },
+        None => None,
+    };
+    let handle = cf.as_ref().map(|cf| cf.handle);
+    let _key_lock = resource.key_locks.lock(cf.as_ref().map_or("default", |cf| &cf.name), &key);
+    let value = match read_counter(&db, handle, &key).and_then(|value| add_delta(value, delta)) {
+        Ok(value) => value,
+        Err(e) => return Ok(counter_error(env, e)),
+    };
+    let result = match handle {
+        Some(handle) => db.put_cf(handle, &key, &value.to_be_bytes()),
+        None => db.put(&key, &value.to_be_bytes()),
+    };
+    match result {
+        Ok(_) => Ok((atoms::ok(), value).encode(env)),
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
+
+fn counter_get<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let key = resource.key_codec.encode(args[1])?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let value = match db.get_pinned(&key) {
+        Ok(Some(v)) => match decode_counter(&v) {
+            Some(value) => (atoms::ok(), value).encode(env),
+            None => counter_error(env, CounterError::NotCounter),
+        },
+        Ok(None) => (atoms::notfound()).encode(env),
+        Err(e) => errors::db_error(env, &e),
+    };
+    Ok(value)
+}
+
+enum CounterError {
+    NotCounter,
+    Overflow,
+    UnknownCf(String),
+    Db(rocksdb::Error),
+}
+
+fn counter_error<'a>(env: Env<'a>, e: CounterError) -> Term<'a> {
+    match e {
+        CounterError::NotCounter => errors::invalid_argument(env, "value is not a counter".to_string()),
+        CounterError::Overflow => errors::invalid_argument(env, "counter overflow".to_string()),
+        CounterError::UnknownCf(name) => unknown_cf(env, &name),
+        CounterError::Db(e) => errors::db_error(env, &e),
+    }
+}
+
+fn decode_counter(bytes: &[u8]) -> Option<i64> {
+    if bytes.len() != 8 {
+        return None;
+    }
+    let mut raw = [0u8; 8];
+    raw.copy_from_slice(bytes);
+    Some(i64::from_be_bytes(raw))
+}
+
+fn read_counter(db: &DB, cf: Option<ColumnFamily>, key: &[u8]) -> Result<i64, CounterError> {
+    let read = match cf {
+        Some(cf) => db.get_pinned_cf(cf, key),
+        None => db.get_pinned(key),
+    };
+    match read {
+        Ok(Some(v)) => decode_counter(&v).ok_or(CounterError::NotCounter),
+        Ok(None) => Ok(0),
+        Err(e) => Err(CounterError::Db(e)),
+    }
+}
+
+fn add_delta(value: i64, delta: i64) -> Result<i64, CounterError> {
+    value.checked_add(delta).ok_or(CounterError::Overflow)
+}
+
+// Deltas of the same counter are summed, then each counter is read once
+// and its new value put into the batch. The caller holds the key locks.
+fn add_counters(db: &DB, batch: &mut WriteBatch, counters: Vec<Counter>) -> Result<(), CounterError> {
+    let mut deltas: BTreeMap<(Option<String>, Vec<u8>), i64> = BTreeMap::new();
+    for counter in counters {
+        let delta = deltas.entry((counter.cf, counter.key)).or_insert(0);
+        *delta = add_delta(*delta, counter.delta)?;
+    }
+    for ((cf, key), delta) in deltas {
+        let handle = match cf {
+            Some(name) => Some(user_cf_handle(db, &name).ok_or(CounterError::UnknownCf(name))?),
+            None => None,
+        };
+        let value = add_delta(read_counter(db, handle, &key)?, delta)?;
+        let result = match handle {
+            Some(handle) => batch.put_cf(handle, &key, &value.to_be_bytes()),
+            None => batch.put(&key, &value.to_be_bytes()),
+        };
+        result.map_err(CounterError::Db)?;
+    }
+    Ok(())
+}
+
+fn set_options<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let opts = match options::decode_mutable(env, args[1])? {
+        Ok(opts) => opts,
+        Err(e) => return Ok(e),
+    };
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    // RocksDB rejects an empty change
+    if opts.is_empty() {
+        return Ok((atoms::ok()).encode(env));
+    }
+    let opts: Vec<(&str, &str)> = opts.iter().map(|(name, value)| (*name, value.as_str())).collect();
+    match db.set_options(&opts) {
         Ok(_) => Ok((atoms::ok()).encode(env)),
-        Err(e) => Ok((atoms::err(), e.to_string()).encode(env)),
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
+
+fn estimate_num_keys<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    Ok(int_property(env, db.property_value(ESTIMATE_NUM_KEYS)))
+}
+
+fn estimate_num_keys_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let cf = match lookup_cf(&db, &resource, args[1])? {
+        Ok(cf) => cf,
+        Err(e) => return Ok(cf_error(env, e)),
+    };
+    Ok(int_property(env, db.property_value_cf(cf.handle, ESTIMATE_NUM_KEYS)))
+}
+
+fn approximate_sizes<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    approximate_sizes_of(env, &resource, |name| db.property_value(name), args[1], args[2])
+}
+
+fn approximate_sizes_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let cf = match lookup_cf(&db, &resource, args[1])? {
+        Ok(cf) => cf,
+        Err(e) => return Ok(cf_error(env, e)),
+    };
+    approximate_sizes_of(env, &resource, |name| db.property_value_cf(cf.handle, name), args[2], args[3])
+}
+
+// A file counts whole for every range it overlaps, the memtables with
+// their whole size for every non-empty range.
+fn approximate_sizes_of<'a, F>(env: Env<'a>, resource: &DbResource, property: F, ranges: Term<'a>, opts: Term<'a>) -> NifResult<Term<'a>>
+    where F: Fn(&str) -> Result<Option<String>, rocksdb::Error>
+{
+    let mut include_memtables = false;
+    let mut include_files = true;
+    let iter: MapIterator = opts.decode()?;
+    for (key, value) in iter {
+        let flag = match key.atom_to_string()?.as_str() {
+            "include_memtables" => &mut include_memtables,
+            "include_files" => &mut include_files,
+            _ => return Ok(errors::error(env, (atoms::unknown_option(), key))),
+        };
+        match value.decode() {
+            Ok(value) => *flag = value,
+            Err(_) => return Ok(errors::error(env, (atoms::invalid_option(), key, value))),
+        }
+    }
+    if !include_memtables && !include_files {
+        return Ok(errors::invalid_argument(env, "include_memtables or include_files must be true".to_string()));
+    }
+    let iter: ListIterator = ranges.decode()?;
+    let mut bounds = Vec::new();
+    for range in iter {
+        let (start, end): (Term, Term) = range.decode()?;
+        bounds.push((resource.key_codec.encode(start)?, resource.key_codec.encode(end)?));
+    }
+
+    let files = if include_files {
+        match sst_files_of(env, &property) {
+            Ok(files) => files,
+            Err(e) => return Ok(e),
+        }
+    } else {
+        Vec::new()
+    };
+    let memtables = if include_memtables {
+        match property(MEMTABLES_SIZE) {
+            Ok(Some(size)) => size.parse::<u64>().unwrap_or(0),
+            Ok(None) => return Ok(errors::error(env, (atoms::not_supported(), "property not available"))),
+            Err(e) => return Ok(errors::db_error(env, &e)),
+        }
+    } else {
+        0
+    };
+    let sizes: Vec<u64> = bounds.iter()
+        .map(|(start, end)| {
+            if start >= end {
+                return 0;
+            }
+            let in_files: u64 = files.iter()
+                .filter(|file| file.overlaps(start, end))
+                .map(|file| file.size)
+                .sum();
+            in_files + memtables
+        })
+        .collect();
+    Ok((atoms::ok(), sizes).encode(env))
+}
+
+// default is the default cf, other cfs are given by name or handle
+fn column_family_metadata<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let cf = if args[1].is_atom() && args[1].atom_to_string()? == "default" {
+        None
+    } else {
+        match lookup_cf(&db, &resource, args[1])? {
+            Ok(cf) => Some(cf),
+            Err(e) => return Ok(cf_error(env, e)),
+        }
+    };
+    let property = |name: &str| match cf {
+        Some(ref cf) => db.property_value_cf(cf.handle, name),
+        None => db.property_value(name),
+    };
+    let levels = match level_stats::collect(property) {
+        Ok(levels) => levels,
+        Err(e) => return Ok(errors::db_error(env, &e)),
+    };
+    let files = match sst_files_of(env, property) {
+        Ok(files) => files,
+        Err(e) => return Ok(e),
+    };
+    let mut terms = Vec::with_capacity(levels.len());
+    for stats in &levels {
+        let mut level_files = Vec::new();
+        for file in files.iter().filter(|file| file.level == stats.level) {
+            level_files.push(sst_file_term(env, file)?);
+        }
+        let mut map = map_new(env)
+            .map_put(atoms::level(), stats.level)?
+            .map_put(atoms::files(), stats.files)?
+            .map_put(atoms::size(), stats.size)?
+            .map_put(atoms::entries(), stats.entries)?
+            .map_put(atoms::sst_files(), level_files)?;
+        if let Some(deletions) = stats.deletions {
+            map = map.map_put(atoms::deletions(), deletions)?;
+        }
+        terms.push(map);
+    }
+    let name = cf.as_ref().map_or("default", |cf| &cf.name);
+    let metadata = map_new(env)
+        .map_put(atoms::name(), name)?
+        .map_put(atoms::size(), levels.iter().map(|stats| stats.size).sum::<u64>())?
+        .map_put(atoms::levels(), terms)?;
+    Ok((atoms::ok(), metadata).encode(env))
+}
+
+// The files of every cf but the internal one, in the order of list_cf/1
+fn live_files<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let names = match DB::list_cf(&Options::default(), &resource.path) {
+        Ok(names) => names,
+        Err(e) => return Ok(errors::db_error(env, &e)),
+    };
+    let mut terms = Vec::new();
+    for name in names {
+        let files = if name == "default" {
+            sst_files_of(env, |property| db.property_value(property))
+        } else {
+            match user_cf_handle(&db, &name) {
+                Some(handle) => sst_files_of(env, |property| db.property_value_cf(handle, property)),
+                None => continue,
+            }
+        };
+        let files = match files {
+            Ok(files) => files,
+            Err(e) => return Ok(e),
+        };
+        for file in &files {
+            terms.push(sst_file_term(env, file)?.map_put(atoms::column_family(), name.as_str())?);
+        }
+    }
+    Ok((atoms::ok(), terms).encode(env))
+}
+
+fn sst_files_of<'a, F>(env: Env<'a>, property: F) -> Result<Vec<sst_files::SstFile>, Term<'a>>
+    where F: Fn(&str) -> Result<Option<String>, rocksdb::Error>
+{
+    match property(sst_files::PROPERTY) {
+        Ok(Some(listing)) => sst_files::parse(&listing).map_err(|msg| errors::corruption(env, msg)),
+        Ok(None) => Err(errors::error(env, (atoms::not_supported(), "property not available"))),
+        Err(e) => Err(errors::db_error(env, &e)),
+    }
+}
+
+fn sst_file_term<'a>(env: Env<'a>, file: &sst_files::SstFile) -> NifResult<Term<'a>> {
+    map_new(env)
+        .map_put(atoms::name(), file.name())?
+        .map_put(atoms::level(), file.level)?
+        .map_put(atoms::size(), file.size)?
+        .map_put(atoms::smallest_key(), to_binary(env, &file.smallest))?
+        .map_put(atoms::largest_key(), to_binary(env, &file.largest))
+}
+
+// Polls the db for the event_listener option until it's closed, the
+// thread keeps the resource, so only close/1 stops it.
+fn watch_events(resource: ResourceArc<DbResource>, listener: LocalPid) {
+    {
+        let db = match resource.read_db() {
+            Some(db) => db,
+            None => return,
+        };
+        let cfs = match DB::list_cf(&Options::default(), &resource.path) {
+            Ok(names) => names.into_iter().filter(|name| name != internal::NAME).collect(),
+            Err(_) => vec!["default".to_string()],
+        };
+        let watched = events::Watched::new(cfs, |cf, name| cf_property(&db, cf, name));
+        *lock_safe(&resource.watched) = Some(watched);
+    }
+    thread::spawn(move || {
+        let mut owned_env = OwnedEnv::new();
+        loop {
+            thread::sleep(events::POLL_INTERVAL);
+            let polled = {
+                let db = match resource.read_db() {
+                    Some(db) => db,
+                    None => return,
+                };
+                match *lock_safe(&resource.watched) {
+                    Some(ref mut watched) => watched.poll(|cf, name| cf_property(&db, cf, name)),
+                    None => return,
+                }
+            };
+            for event in &polled {
+                owned_env.send_and_clear(&listener, |env| {
+                    event_term(env, &resource, event).expect("keys of a new map")
+                });
+            }
+        }
+    });
+}
+
+// {rocker_event, Db, Name, Info}
+fn event_term<'a>(env: Env<'a>, resource: &ResourceArc<DbResource>, event: &events::Event) -> NifResult<Term<'a>> {
+    let (name, info) = match *event {
+        events::Event::FlushCompleted { ref cf, ref files } => {
+            let files = files.iter()
+                .map(|file| sst_file_term(env, file))
+                .collect::<NifResult<Vec<Term>>>()?;
+            let info = map_new(env)
+                .map_put(atoms::column_family(), cf.as_str())?
+                .map_put(atoms::sst_files(), files)?;
+            (atoms::flush_completed(), info)
+        }
+        events::Event::BackgroundError { count } => {
+            (atoms::background_error(), map_new(env).map_put(atoms::count(), count)?)
+        }
+        events::Event::StallConditionsChanged(stall) => {
+            let info = map_new(env)
+                .map_put(atoms::write_stopped(), stall.write_stopped)?
+                .map_put(atoms::delayed_write_rate(), stall.delayed_write_rate)?;
+            (atoms::stall_conditions_changed(), info)
+        }
+    };
+    Ok((atoms::rocker_event(), resource, name, info).encode(env))
+}
+
+// None if the cf is gone or RocksDB doesn't know the property
+fn cf_property(db: &DB, cf: &str, name: &str) -> Option<String> {
+    let value = if cf == "default" {
+        db.property_value(name)
+    } else {
+        db.property_value_cf(user_cf_handle(db, cf)?, name)
+    };
+    value.ok()?
+}
+
+const ESTIMATE_NUM_KEYS: &str = "rocksdb.estimate-num-keys";
+const MEMTABLES_SIZE: &str = "rocksdb.cur-size-all-mem-tables";
+
+fn int_property<'a>(env: Env<'a>, value: Result<Option<String>, rocksdb::Error>) -> Term<'a> {
+    match value {
+        Ok(Some(value)) => match value.parse::<u64>() {
+            Ok(n) => (atoms::ok(), n).encode(env),
+            Err(_) => errors::corruption(env, format!("invalid property value: {}", value)),
+        },
+        Ok(None) => errors::error(env, (atoms::not_supported(), "property not available")),
+        Err(e) => errors::db_error(env, &e),
     }
 }
 
 fn iterator_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
-    let cf: String = args[1].decode()?;
     let mode_terms: Vec<Term> = ::rustler::types::tuple::get_tuple(args[2])?;
-    let db = resource.db.read().unwrap();
-    let cf_handler = db.cf_handle(&cf.as_str()).unwrap();
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let cf = match lookup_cf(&db, &resource, args[1])? {
+        Ok(cf) => cf,
+        Err(e) => return Ok(cf_error(env, e)),
+    };
+    let cf_handler = cf.handle;
     let mut iterator = db.iterator_cf(cf_handler, IteratorMode::Start);
     if mode_terms.len() >= 1 {
         let mode: String = mode_terms[0].atom_to_string()?;
         match mode.as_str() {
             "end" => iterator = db.iterator_cf(cf_handler, IteratorMode::End),
             "from" => {
-                let from: Binary = mode_terms[1].decode()?;
+                let from = resource.key_codec.encode(mode_terms[1])?;
                 if mode_terms.len() == 3 {
                     let direction: String = mode_terms[2].atom_to_string()?;
                     iterator = match direction.as_str() {
@@ -620,10 +1830,17 @@ fn iterator_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
         }
     }
 
+    let iterator = match iterator {
+        Ok(iterator) => iterator,
+        Err(e) => return Ok(errors::db_error(env, &e)),
+    };
     let resource = ResourceArc::new(IteratorResource {
-        iter: RwLock::new(
-            iterator.unwrap(),
-        ),
+        iter: resource.track_iterator(iterator, Some(&cf.name)),
+        db: resource.clone(),
+        key_codec: resource.key_codec,
+        value_codec: cf.value_codec,
+        cf: Some(cf.name),
+        overlay: None,
     });
 
     Ok((atoms::ok(), resource.encode(env)).encode(env))
@@ -631,18 +1848,873 @@ fn iterator_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
 
 fn prefix_iterator_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let resource: ResourceArc<DbResource> = args[0].decode()?;
-    let cf: String = args[1].decode()?;
     let prefix: Binary = args[2].decode()?;
 
-    let db = resource.db.read().unwrap();
-    let cf_handler = db.cf_handle(&cf.as_str()).unwrap();
-    let iterator = db.prefix_iterator_cf(cf_handler, &prefix);
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let cf = match lookup_cf(&db, &resource, args[1])? {
+        Ok(cf) => cf,
+        Err(e) => return Ok(cf_error(env, e)),
+    };
+    let iterator = db.prefix_iterator_cf(cf.handle, &prefix);
+
+    let iterator = match iterator {
+        Ok(iterator) => iterator,
+        Err(e) => return Ok(errors::db_error(env, &e)),
+    };
+    let resource = ResourceArc::new(IteratorResource {
+        iter: resource.track_iterator(iterator, Some(&cf.name)),
+        db: resource.clone(),
+        key_codec: resource.key_codec,
+        value_codec: cf.value_codec,
+        cf: Some(cf.name),
+        overlay: None,
+    });
+
+    Ok((atoms::ok(), resource.encode(env)).encode(env))
+}
+
+// A column family given by name or by a handle from cf_handle/2
+struct Cf {
+    handle: ColumnFamily,
+    name: String,
+    value_codec: ValueCodec,
+}
+
+enum CfError {
+    Unknown(String),
+    Dropped,
+}
+
+fn lookup_cf(db: &DB, resource: &ResourceArc<DbResource>, term: Term) -> NifResult<Result<Cf, CfError>> {
+    if let Ok(cf) = term.decode::<ResourceArc<CfResource>>() {
+        // a handle of another db would point into the wrong instance
+        if !ptr::eq(&*cf.db, &**resource) {
+            return Err(rustler::Error::BadArg);
+        }
+        let handle = cf.handle.read_safe();
+        return Ok(match *handle {
+            Some(ref handle) => Ok(Cf {
+                handle: handle.0,
+                name: cf.name.clone(),
+                value_codec: cf.value_codec,
+            }),
+            None => Err(CfError::Dropped),
+        });
+    }
+    let name: String = term.decode()?;
+    match user_cf_handle(db, &name) {
+        Some(handle) => Ok(Ok(Cf {
+            handle,
+            value_codec: resource.cf_value_codec(&name),
+            name,
+        })),
+        None => Ok(Err(CfError::Unknown(name))),
+    }
+}
+
+// the internal cf isn't found by name
+fn user_cf_handle(db: &DB, name: &str) -> Option<ColumnFamily> {
+    if name == internal::NAME {
+        None
+    } else {
+        db.cf_handle(name)
+    }
+}
+
+fn reserved_cf<'a>(env: Env<'a>) -> Term<'a> {
+    errors::invalid_argument(env, format!("column family name {} is reserved", internal::NAME))
+}
+
+fn cf_error<'a>(env: Env<'a>, e: CfError) -> Term<'a> {
+    match e {
+        CfError::Unknown(name) => unknown_cf(env, &name),
+        CfError::Dropped => errors::error(env, atoms::cf_dropped()),
+    }
+}
+
+
+// Results are prepended to the accumulator, multi_get/2 reverses them
+// once the list is done and calls it again on {continue, Acc, Rest}.
+fn multi_get_slice<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let mut list = args[1];
+    let mut acc = args[2];
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let mut timeslice = Timeslice::new(env);
+    while !list.is_empty_list() {
+        if timeslice.tick() {
+            return Ok((atoms::continue_(), acc, list).encode(env));
+        }
+        let (elem, tail) = list.list_get_cell()?;
+        list = tail;
+        let key = resource.key_codec.encode(elem)?;
+        acc = acc.list_prepend(get_value(env, &resource, &db, resource.value_codec, &key));
+    }
+    Ok((atoms::ok(), acc).encode(env))
+}
+
+fn get_async<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let key = resource.key_codec.encode(args[1])?.into_owned();
+    async_pool::reply(env, args[2], move |env| {
+        let db = match resource.read_db() {
+            Some(db) => db,
+            None => return closed(env),
+        };
+        get_value(env, &resource, &db, resource.value_codec, &key)
+    });
+    Ok((atoms::ok()).encode(env))
+}
+
+fn put_async<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let key = resource.key_codec.encode(args[1])?.into_owned();
+    let value = resource.value_codec.encode(args[2])?.into_owned();
+    async_pool::reply(env, args[3], move |env| {
+        let db = match resource.read_db() {
+            Some(db) => db,
+            None => return closed(env),
+        };
+        put_value(env, &db, &key, &value)
+    });
+    Ok((atoms::ok()).encode(env))
+}
+
+fn tx_async<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    if !args[1].is_list() {
+        return Err(rustler::Error::BadArg);
+    }
+    // the list is only copied here, the batch is built on the pool
+    // and errors of the ops are sent as the reply
+    async_pool::reply_with(env, args[2], args[1], move |env, list| {
+        let db = match resource.read_db() {
+            Some(db) => db,
+            None => return closed(env),
+        };
+        match build_batch(&db, &resource, list) {
+            Ok(Ok(builder)) => write_tx(env, &db, &resource, builder),
+            Ok(Err(e)) => tx_error(env, e),
+            Err(_) => errors::error(env, atoms::badarg()),
+        }
+    });
+    Ok((atoms::ok()).encode(env))
+}
+
+fn multi_get_async<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let iter: ListIterator = args[1].decode()?;
+    let mut keys: Vec<Vec<u8>> = Vec::new();
+    for elem in iter {
+        keys.push(resource.key_codec.encode(elem)?.into_owned());
+    }
+    async_pool::reply(env, args[2], move |env| {
+        let db = match resource.read_db() {
+            Some(db) => db,
+            None => return closed(env),
+        };
+        let results: Vec<Term> = keys.iter()
+            .map(|key| get_value(env, &resource, &db, resource.value_codec, key))
+            .collect();
+        (atoms::ok(), results).encode(env)
+    });
+    Ok((atoms::ok()).encode(env))
+}
+
+fn latest_sequence_number<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    Ok((atoms::ok(), db.latest_sequence_number()).encode(env))
+}
+
+fn get_updates_since<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let seq_number: u64 = args[1].decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    match db.get_updates_since(seq_number) {
+        Ok(iter) => {
+            let resource = ResourceArc::new(WalIteratorResource {
+                iter: resource.track_wal_iterator(iter),
+                db: resource.clone(),
+            });
+            Ok((atoms::ok(), resource.encode(env)).encode(env))
+        }
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
+
+fn wal_next<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<WalIteratorResource> = args[0].decode()?;
+    let mut iter = resource.iter.write_safe();
+    let iter = match *iter {
+        Some(ref mut iter) => iter,
+        None => return Ok(closed(env)),
+    };
+    // waits for the internal cf being created, its first writes included
+    let internal_cf = *resource.db.internal_cf.read_safe();
+    // batches with only internal ops are skipped
+    for (seq_number, batch) in iter {
+        let ops = match write_batch::parse(batch.data()) {
+            Ok(ops) => ops,
+            Err(e) => return Ok(errors::corruption(env, e)),
+        };
+        let ops: Vec<Term<'a>> = ops.iter()
+            .filter(|op| Some(internal::cf_of(op)) != internal_cf)
+            .map(|op| batch_op_to_term(env, op))
+            .collect();
+        if !ops.is_empty() {
+            return Ok((seq_number, ops).encode(env));
+        }
+    }
+    Ok((atoms::ok()).encode(env))
+}
+
+fn batch_op_to_term<'a>(env: Env<'a>, op: &write_batch::BatchOp) -> Term<'a> {
+    match *op {
+        write_batch::BatchOp::Put(cf, key, value) => {
+            (atoms::put(), cf, to_binary(env, key), to_binary(env, value)).encode(env)
+        }
+        write_batch::BatchOp::Delete(cf, key) => {
+            (atoms::delete(), cf, to_binary(env, key)).encode(env)
+        }
+        write_batch::BatchOp::Merge(cf, key, value) => {
+            (atoms::merge(), cf, to_binary(env, key), to_binary(env, value)).encode(env)
+        }
+        write_batch::BatchOp::DeleteRange(cf, start, end) => {
+            (atoms::delete_range(), cf, to_binary(env, start), to_binary(env, end)).encode(env)
+        }
+    }
+}
+
+fn write_batch_data<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let iter: ListIterator = args[0].decode()?;
+    let mut ops = Vec::new();
+    for elem in iter {
+        let terms: Vec<Term> = ::rustler::types::tuple::get_tuple(elem)?;
+        if terms.len() < 3 {
+            return Err(rustler::Error::BadArg);
+        }
+        let op: String = terms[0].atom_to_string()?;
+        let cf: u32 = terms[1].decode()?;
+        let key: Binary = terms[2].decode()?;
+        let op = match (op.as_str(), terms.len()) {
+            ("put", 4) => write_batch::BatchOp::Put(cf, key.as_slice(), terms[3].decode::<Binary>()?.as_slice()),
+            ("delete", 3) => write_batch::BatchOp::Delete(cf, key.as_slice()),
+            ("merge", 4) => write_batch::BatchOp::Merge(cf, key.as_slice(), terms[3].decode::<Binary>()?.as_slice()),
+            ("delete_range", 4) => write_batch::BatchOp::DeleteRange(cf, key.as_slice(), terms[3].decode::<Binary>()?.as_slice()),
+            _ => return Err(rustler::Error::BadArg),
+        };
+        ops.push(op);
+    }
+    let data = write_batch::serialize(&ops);
+    Ok((atoms::ok(), to_binary(env, &data)).encode(env))
+}
+
+fn apply_batch_data<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let seq_number: u64 = args[1].decode()?;
+    let data: Binary = args[2].decode()?;
+    let count = match write_batch::count(&data) {
+        Ok(count) if count > 0 => u64::from(count),
+        Ok(_) => return Err(rustler::Error::BadArg),
+        Err(e) => return Ok(errors::invalid_argument(env, e)),
+    };
+    let last = match seq_number.checked_add(count - 1) {
+        Some(last) => last,
+        None => return Err(rustler::Error::BadArg),
+    };
+    // keeps the marker check and the write atomic between appliers
+    let _apply = lock_safe(&resource.apply_lock);
+    let internal_cf = match resource.internal_cf() {
+        Some(Ok(id)) => id,
+        Some(Err(msg)) => return Ok(errors::status(env, msg)),
+        None => return Ok(closed(env)),
+    };
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let handle = match db.cf_handle(internal::NAME) {
+        Some(handle) => handle,
+        None => return Ok(closed(env)),
+    };
+    // The last leader seqno applied is written along with every batch, so
+    // the follower's own writes and its seqnos don't matter, and the leader
+    // may leave gaps, as writes with disable_wal do.
+    let applied = match db.get_cf(handle, internal::APPLIED_SEQNO) {
+        Ok(applied) => internal::decode_seqno(applied.as_ref().map(|v| &v[..])),
+        Err(e) => return Ok(errors::db_error(env, &e)),
+    };
+    if let Some(applied) = applied {
+        if last <= applied {
+            return Ok((atoms::skipped()).encode(env));
+        }
+    }
+    match write_batch::parse(&data) {
+        Ok(ops) => {
+            if ops.iter().any(|op| internal::cf_of(op) == internal_cf) {
+                return Ok(errors::invalid_argument(env, format!("batch writes to column family {}, {} on this db", internal_cf, internal::NAME)));
+            }
+        }
+        Err(e) => return Ok(errors::invalid_argument(env, e)),
+    }
+    let mut batch = WriteBatch::from_data(&data);
+    if let Err(e) = batch.put_cf(handle, internal::APPLIED_SEQNO, &last.to_be_bytes()) {
+        return Ok(errors::db_error(env, &e));
+    }
+    match db.write(batch) {
+        Ok(_) => Ok((atoms::ok()).encode(env)),
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
+
+fn applied_sequence_number<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    // no internal cf, nothing applied yet
+    let handle = match db.cf_handle(internal::NAME) {
+        Some(handle) => handle,
+        None => return Ok((atoms::notfound()).encode(env)),
+    };
+    match db.get_cf(handle, internal::APPLIED_SEQNO) {
+        Ok(applied) => match internal::decode_seqno(applied.as_ref().map(|v| &v[..])) {
+            Some(applied) => Ok((atoms::ok(), applied).encode(env)),
+            None => Ok((atoms::notfound()).encode(env)),
+        },
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
+
+fn to_binary<'a>(env: Env<'a>, bytes: &[u8]) -> Term<'a> {
+    let mut bin = OwnedBinary::new(bytes.len()).unwrap();
+    bin.clone_from_slice(bytes);
+    bin.release(env).encode(env)
+}
+
+fn key_encode<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let encoded = key_codec::encode(args[0])?;
+    let mut bin = OwnedBinary::new(encoded.len()).unwrap();
+    bin.clone_from_slice(&encoded);
+    Ok(bin.release(env).encode(env))
+}
+
+fn key_decode<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let bin: Binary = args[0].decode()?;
+    key_codec::decode(env, bin.as_slice())
+}
+
+
+fn batch_new<'a>(env: Env<'a>, _args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let batch = ResourceArc::new(BatchResource::new(false));
+    Ok((atoms::ok(), batch.encode(env)).encode(env))
+}
+
+fn batch_put<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    let key: Binary = args[1].decode()?;
+    let value: Binary = args[2].decode()?;
+    Ok(with_batch(env, &resource, |batch, index| {
+        batch.put(key.as_slice(), value.as_slice())?;
+        if let Some(index) = index {
+            index.stage(key.as_slice(), Some(value.as_slice()));
+        }
+        Ok(())
+    }))
+}
+
+fn batch_put_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    let cf: ResourceArc<CfResource> = args[1].decode()?;
+    let key: Binary = args[2].decode()?;
+    let value: Binary = args[3].decode()?;
+    with_batch_cf(env, &resource, &cf, |batch, handle| {
+        batch.put_cf(handle, key.as_slice(), value.as_slice())
+    })
+}
+
+fn batch_delete<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    let key: Binary = args[1].decode()?;
+    Ok(with_batch(env, &resource, |batch, index| {
+        batch.delete(key.as_slice())?;
+        if let Some(index) = index {
+            index.stage(key.as_slice(), None);
+        }
+        Ok(())
+    }))
+}
+
+fn batch_delete_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    let cf: ResourceArc<CfResource> = args[1].decode()?;
+    let key: Binary = args[2].decode()?;
+    with_batch_cf(env, &resource, &cf, |batch, handle| batch.delete_cf(handle, key.as_slice()))
+}
+
+fn batch_merge<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    let key: Binary = args[1].decode()?;
+    let value: Binary = args[2].decode()?;
+    if resource.indexed {
+        return Ok(not_indexable(env, "merge"));
+    }
+    Ok(with_batch(env, &resource, |batch, _| batch.merge(key.as_slice(), value.as_slice())))
+}
+
+fn batch_delete_range<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    let from: Binary = args[1].decode()?;
+    let to: Binary = args[2].decode()?;
+    if resource.indexed {
+        return Ok(not_indexable(env, "delete_range"));
+    }
+    Ok(with_batch(env, &resource, |batch, _| batch.delete_range(from.as_slice(), to.as_slice())))
+}
+
+fn batch_count<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    let state = lock_safe(&resource.state);
+    match state.batch {
+        Some(ref batch) => Ok((atoms::ok(), batch.len()).encode(env)),
+        None => Ok(consumed(env)),
+    }
+}
+
+fn batch_data_size<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    let state = lock_safe(&resource.state);
+    match state.batch {
+        Some(ref batch) => Ok((atoms::ok(), batch.size_in_bytes()).encode(env)),
+        None => Ok(consumed(env)),
+    }
+}
+
+fn batch_clear<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    Ok(with_batch(env, &resource, |batch, index| {
+        batch.clear()?;
+        if let Some(index) = index {
+            index.clear();
+        }
+        Ok(())
+    }))
+}
+
+fn batch_set_savepoint<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    Ok(with_batch(env, &resource, |batch, index| {
+        batch.set_save_point();
+        if let Some(index) = index {
+            index.set_savepoint();
+        }
+        Ok(())
+    }))
+}
+
+fn batch_rollback_to_savepoint<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    Ok(with_batch(env, &resource, |batch, index| {
+        batch.rollback_to_save_point()?;
+        if let Some(index) = index {
+            index.rollback_to_savepoint();
+        }
+        Ok(())
+    }))
+}
+
+fn batch_pop_savepoint<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    Ok(with_batch(env, &resource, |batch, index| {
+        batch.pop_save_point()?;
+        if let Some(index) = index {
+            index.pop_savepoint();
+        }
+        Ok(())
+    }))
+}
+
+fn with_batch<'a, F>(env: Env<'a>, resource: &BatchResource, f: F) -> Term<'a>
+    where F: FnOnce(&mut WriteBatch, Option<&mut Index>) -> Result<(), rocksdb::Error>
+{
+    let mut state = lock_safe(&resource.state);
+    let state = &mut *state;
+    if state.prepared {
+        return errors::error(env, atoms::prepared());
+    }
+    let batch = match state.batch {
+        Some(ref mut batch) => batch,
+        None => return consumed(env),
+    };
+    match f(batch, state.index.as_mut()) {
+        Ok(_) => (atoms::ok()).encode(env),
+        Err(e) => errors::db_error(env, &e),
+    }
+}
+
+fn with_batch_cf<'a, F>(env: Env<'a>, resource: &BatchResource, cf: &ResourceArc<CfResource>, f: F) -> NifResult<Term<'a>>
+    where F: FnOnce(&mut WriteBatch, ColumnFamily) -> Result<(), rocksdb::Error>
+{
+    let mut state = lock_safe(&resource.state);
+    // one batch is written to one db
+    if let Some(ref db) = state.db {
+        if !ptr::eq(&**db, &*cf.db) {
+            return Err(rustler::Error::BadArg);
+        }
+    }
+    if cf.db.key_codec != KeyCodec::Raw || cf.value_codec != ValueCodec::Raw {
+        return Ok(coded_batch(env));
+    }
+    // held until the op is added, drop_cf/close wait for it
+    let handle = cf.handle.read_safe();
+    let handle = match *handle {
+        Some(ref handle) => handle.0,
+        None => return Ok(errors::error(env, atoms::cf_dropped())),
+    };
+    if state.prepared {
+        return Ok(errors::error(env, atoms::prepared()));
+    }
+    let result = match state.batch {
+        Some(ref mut batch) => f(batch, handle),
+        None => return Ok(consumed(env)),
+    };
+    match result {
+        Ok(_) => {
+            state.db = Some(cf.db.clone());
+            Ok((atoms::ok()).encode(env))
+        }
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
+
+// The index only follows puts and deletes on the default cf
+fn not_indexable<'a>(env: Env<'a>, op: &str) -> Term<'a> {
+    errors::with_message(env, atoms::not_supported(), format!("{} in an indexed batch", op))
+}
+
+// Batches take binaries as they are stored, a codec would be bypassed
+fn raw_codecs(resource: &DbResource) -> bool {
+    resource.key_codec == KeyCodec::Raw && resource.value_codec == ValueCodec::Raw
+}
+
+fn coded_batch<'a>(env: Env<'a>) -> Term<'a> {
+    errors::with_message(env, atoms::not_supported(), "write batches with a key or value codec".to_string())
+}
+
+fn consumed<'a>(env: Env<'a>) -> Term<'a> {
+    errors::error(env, atoms::consumed())
+}
+
+fn write_batch<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let batch: ResourceArc<BatchResource> = args[1].decode()?;
+    let opts: WriteOptions = match options::decode_write(env, args[2])? {
+        Ok(opts) => opts,
+        Err(e) => return Ok(e),
+    };
+    if !raw_codecs(&resource) {
+        return Ok(coded_batch(env));
+    }
+    let mut state = lock_safe(&batch.state);
+    if let Some(ref db) = state.db {
+        if !ptr::eq(&**db, &*resource) {
+            return Err(rustler::Error::BadArg);
+        }
+    }
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    // RocksDB takes the batch it writes, a failed write keeps ours
+    let mut write = match state.batch {
+        Some(ref batch) => WriteBatch::from_data(batch.data()),
+        None => return Ok(consumed(env)),
+    };
+    // the record of a prepared tx is removed by the write of its batch
+    let _prepare = if state.prepared { Some(lock_safe(&resource.prepare_lock)) } else { None };
+    if state.prepared {
+        let key = internal::prepared_key(state.name.as_ref().expect("named before prepare"));
+        let handle = db.cf_handle(internal::NAME).expect("created by prepare");
+        match db.get_cf(handle, &key) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                // committed or rolled back through another resource
+                state.batch = None;
+                return Ok(consumed(env));
+            }
+            Err(e) => return Ok(errors::db_error(env, &e)),
+        }
+        if let Err(e) = write.delete_cf(handle, &key) {
+            return Ok(errors::db_error(env, &e));
+        }
+    }
+    if let Err(e) = db.write_opt(write, &opts) {
+        return Ok(errors::db_error(env, &e));
+    }
+    state.batch = None;
+    state.index = None;
+    Ok((atoms::ok()).encode(env))
+}
+
+
+fn wbwi_new<'a>(env: Env<'a>, _args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let batch = ResourceArc::new(BatchResource::new(true));
+    Ok((atoms::ok(), batch.encode(env)).encode(env))
+}
+
+fn wbwi_get<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let batch: ResourceArc<BatchResource> = args[0].decode()?;
+    let resource: ResourceArc<DbResource> = args[1].decode()?;
+    let key: Binary = args[2].decode()?;
+    if !raw_codecs(&resource) {
+        return Ok(coded_batch(env));
+    }
+    let state = match indexed_state(&batch, &resource)? {
+        Some(state) => state,
+        None => return Ok(consumed(env)),
+    };
+    match state.index.as_ref().expect("indexed until written").get(key.as_slice()) {
+        Some(Some(value)) => return Ok((atoms::ok(), to_binary(env, value)).encode(env)),
+        Some(None) => return Ok((atoms::notfound()).encode(env)),
+        None => {}
+    }
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    Ok(get_value(env, &resource, &db, ValueCodec::Raw, key.as_slice()))
+}
+
+fn wbwi_iterator<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let batch: ResourceArc<BatchResource> = args[0].decode()?;
+    let resource: ResourceArc<DbResource> = args[1].decode()?;
+    let mode_terms: Vec<Term> = ::rustler::types::tuple::get_tuple(args[2])?;
+    let mut from: Option<Binary> = None;
+    let mut reverse = false;
+    if let Some(mode) = mode_terms.first() {
+        match mode.atom_to_string()?.as_str() {
+            "end" => reverse = true,
+            "from" => {
+                from = Some(mode_terms.get(1).ok_or(rustler::Error::BadArg)?.decode()?);
+                if let Some(direction) = mode_terms.get(2) {
+                    reverse = direction.atom_to_string()? == "reverse";
+                }
+            }
+            _ => {}
+        }
+    }
+    let from = from.as_ref().map(|from| from.as_slice());
+    if !raw_codecs(&resource) {
+        return Ok(coded_batch(env));
+    }
+
+    let state = match indexed_state(&batch, &resource)? {
+        Some(state) => state,
+        None => return Ok(consumed(env)),
+    };
+    let overlay = state.index.as_ref().expect("indexed until written").overlay(from, reverse);
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let iterator = match (from, reverse) {
+        (None, false) => db.iterator(IteratorMode::Start),
+        (None, true) => db.iterator(IteratorMode::End),
+        (Some(from), false) => db.iterator(IteratorMode::From(from, Direction::Forward)),
+        (Some(from), true) => db.iterator(IteratorMode::From(from, Direction::Reverse)),
+    };
 
     let resource = ResourceArc::new(IteratorResource {
-        iter: RwLock::new(
-            iterator.unwrap(),
-        ),
+        iter: resource.track_iterator(iterator, None),
+        db: resource.clone(),
+        cf: None,
+        key_codec: KeyCodec::Raw,
+        value_codec: ValueCodec::Raw,
+        overlay: Some(Mutex::new(overlay)),
     });
 
     Ok((atoms::ok(), resource.encode(env)).encode(env))
 }
+
+// None once written, badarg for plain batches and ones tied to another db
+fn indexed_state<'b>(batch: &'b BatchResource, resource: &ResourceArc<DbResource>) -> NifResult<Option<MutexGuard<'b, BatchState>>> {
+    if !batch.indexed {
+        return Err(rustler::Error::BadArg);
+    }
+    let state = lock_safe(&batch.state);
+    if let Some(ref db) = state.db {
+        if !ptr::eq(&**db, &**resource) {
+            return Err(rustler::Error::BadArg);
+        }
+    }
+    if state.batch.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(state))
+}
+
+
+fn tx_set_name<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<BatchResource> = args[0].decode()?;
+    let name: Binary = args[1].decode()?;
+    if name.is_empty() {
+        return Err(rustler::Error::BadArg);
+    }
+    let mut state = lock_safe(&resource.state);
+    if state.batch.is_none() {
+        return Ok(consumed(env));
+    }
+    if state.name.is_some() {
+        return Ok(errors::invalid_argument(env, "transaction already has a name".to_string()));
+    }
+    state.name = Some(name.as_slice().to_vec());
+    Ok((atoms::ok()).encode(env))
+}
+
+// The batch is stored synced under its name in the internal cf, it
+// survives a crash until write_batch/3 commits it or tx_rollback/2 drops it.
+fn tx_prepare<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let batch: ResourceArc<BatchResource> = args[1].decode()?;
+    if !raw_codecs(&resource) {
+        return Ok(coded_batch(env));
+    }
+    let mut state = lock_safe(&batch.state);
+    if let Some(ref db) = state.db {
+        if !ptr::eq(&**db, &*resource) {
+            return Err(rustler::Error::BadArg);
+        }
+    }
+    if state.prepared {
+        return Ok(errors::error(env, atoms::prepared()));
+    }
+    let data = match state.batch {
+        Some(ref batch) => batch.data().to_vec(),
+        None => return Ok(consumed(env)),
+    };
+    let name = match state.name {
+        Some(ref name) => name.clone(),
+        None => return Ok(errors::invalid_argument(env, "transaction has no name".to_string())),
+    };
+    match resource.internal_cf() {
+        Some(Ok(_)) => {}
+        Some(Err(msg)) => return Ok(errors::status(env, msg)),
+        None => return Ok(closed(env)),
+    }
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    let handle = match db.cf_handle(internal::NAME) {
+        Some(handle) => handle,
+        None => return Ok(closed(env)),
+    };
+    let key = internal::prepared_key(&name);
+    let _prepare = lock_safe(&resource.prepare_lock);
+    match db.get_cf(handle, &key) {
+        Ok(None) => {}
+        Ok(Some(_)) => return Ok(errors::invalid_argument(env, "transaction name in use".to_string())),
+        Err(e) => return Ok(errors::db_error(env, &e)),
+    }
+    match write_synced(&db, |record| record.put_cf(handle, &key, &data)) {
+        Ok(_) => {
+            state.prepared = true;
+            state.db = Some(resource.clone());
+            Ok((atoms::ok()).encode(env))
+        }
+        Err(e) => Ok(errors::db_error(env, &e)),
+    }
+}
+
+fn write_synced<F>(db: &DB, f: F) -> Result<(), rocksdb::Error>
+    where F: FnOnce(&mut WriteBatch) -> Result<(), rocksdb::Error>
+{
+    let mut batch = WriteBatch::default();
+    f(&mut batch)?;
+    let mut opts = WriteOptions::default();
+    opts.set_sync(true);
+    db.write_opt(batch, &opts)
+}
+
+fn tx_rollback<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let batch: ResourceArc<BatchResource> = args[1].decode()?;
+    let mut state = lock_safe(&batch.state);
+    if let Some(ref db) = state.db {
+        if !ptr::eq(&**db, &*resource) {
+            return Err(rustler::Error::BadArg);
+        }
+    }
+    if state.batch.is_none() {
+        return Ok(consumed(env));
+    }
+    if state.prepared {
+        let db = match resource.read_db() {
+            Some(db) => db,
+            None => return Ok(closed(env)),
+        };
+        let handle = db.cf_handle(internal::NAME).expect("created by prepare");
+        let key = internal::prepared_key(state.name.as_ref().expect("named before prepare"));
+        let _prepare = lock_safe(&resource.prepare_lock);
+        if let Err(e) = write_synced(&db, |record| record.delete_cf(handle, &key)) {
+            return Ok(errors::db_error(env, &e));
+        }
+    }
+    state.batch = None;
+    state.index = None;
+    Ok((atoms::ok()).encode(env))
+}
+
+fn get_all_prepared_transactions<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let resource: ResourceArc<DbResource> = args[0].decode()?;
+    let db = match resource.read_db() {
+        Some(db) => db,
+        None => return Ok(closed(env)),
+    };
+    // nothing was prepared without the internal cf
+    let handle = match db.cf_handle(internal::NAME) {
+        Some(handle) => handle,
+        None => return Ok((atoms::ok(), Vec::<Term<'a>>::new()).encode(env)),
+    };
+    let _prepare = lock_safe(&resource.prepare_lock);
+    let records = match db.iterator_cf(handle, IteratorMode::From(internal::PREPARED, Direction::Forward)) {
+        Ok(records) => records,
+        Err(e) => return Ok(errors::db_error(env, &e)),
+    };
+    let mut txs: Vec<Term<'a>> = Vec::new();
+    for (key, data) in records {
+        if !key.starts_with(internal::PREPARED) {
+            break;
+        }
+        let name = key[internal::PREPARED.len()..].to_vec();
+        if write_batch::count(&data).is_err() {
+            return Ok(errors::corruption(env, "malformed prepared transaction".to_string()));
+        }
+        let batch = ResourceArc::new(BatchResource {
+            indexed: false,
+            state: Mutex::new(BatchState {
+                batch: Some(WriteBatch::from_data(&data)),
+                index: None,
+                db: Some(resource.clone()),
+                name: Some(name.clone()),
+                prepared: true,
+            }),
+        });
+        txs.push((to_binary(env, &name), batch).encode(env));
+    }
+    Ok((atoms::ok(), txs).encode(env))
+}
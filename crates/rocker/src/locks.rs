@@ -0,0 +1,60 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+const KEY_STRIPES: usize = 64;
+
+// A panic while a lock is held can't leave RocksDB in a broken state,
+// so poisoned locks are recovered instead of failing every later call.
+pub trait RwLockExt<T> {
+    fn read_safe(&self) -> RwLockReadGuard<'_, T>;
+    fn write_safe(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T> RwLockExt<T> for RwLock<T> {
+    fn read_safe(&self) -> RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write_safe(&self) -> RwLockWriteGuard<'_, T> {
+        self.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub fn lock_safe<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Conditional writes of a key run under one of a fixed set of mutexes,
+// keys hashing to the same stripe wait on each other.
+pub struct KeyLocks {
+    stripes: Vec<Mutex<()>>,
+}
+
+impl Default for KeyLocks {
+    fn default() -> KeyLocks {
+        KeyLocks {
+            stripes: (0..KEY_STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+}
+
+impl KeyLocks {
+    pub fn stripe(&self, cf: &str, key: &[u8]) -> usize {
+        let mut hasher = DefaultHasher::new();
+        cf.hash(&mut hasher);
+        key.hash(&mut hasher);
+        hasher.finish() as usize % KEY_STRIPES
+    }
+
+    pub fn lock(&self, cf: &str, key: &[u8]) -> MutexGuard<'_, ()> {
+        lock_safe(&self.stripes[self.stripe(cf, key)])
+    }
+
+    // in stripe order, so two callers can't wait on each other
+    pub fn lock_all(&self, mut stripes: Vec<usize>) -> Vec<MutexGuard<'_, ()>> {
+        stripes.sort_unstable();
+        stripes.dedup();
+        stripes.into_iter().map(|stripe| lock_safe(&self.stripes[stripe])).collect()
+    }
+}
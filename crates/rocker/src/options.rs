@@ -0,0 +1,283 @@
+use rocksdb::{DBCompactionStyle, Options, WriteOptions};
+use rustler::{Decoder, Encoder, Env, NifResult, Term};
+use rustler::types::atom::Atom;
+use rustler::types::map::{map_new, MapIterator};
+use rustler::types::LocalPid;
+
+use crate::atoms;
+use crate::errors;
+use crate::key_codec::KeyCodec;
+use crate::value_codec::ValueCodec;
+
+// Options of `open/2` and `create_cf/3`, the codec of the keys and the
+// write ahead log only make sense for the whole db.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scope {
+    Db,
+    Cf,
+}
+
+impl Scope {
+    pub fn from_atom(name: &str) -> Option<Scope> {
+        match name {
+            "db" => Some(Scope::Db),
+            "cf" => Some(Scope::Cf),
+            _ => None,
+        }
+    }
+}
+
+pub struct Decoded<'a> {
+    pub opts: Options,
+    pub key_codec: KeyCodec,
+    pub value_codec: Option<ValueCodec>,
+    pub binary_threshold: usize,
+    // gets the events of the db, see events.rs
+    pub event_listener: Option<LocalPid>,
+    effective: Vec<(Term<'a>, Term<'a>)>,
+}
+
+impl<'a> Decoded<'a> {
+    // every option of the scope with the value it will have, for debugging
+    pub fn effective(&self, env: Env<'a>, scope: Scope) -> NifResult<Term<'a>> {
+        let mut map = map_new(env);
+        for &(name, ref db, ref cf) in KNOWN {
+            let default = match scope {
+                Scope::Db => db,
+                Scope::Cf => match cf {
+                    Some(default) => default,
+                    None => continue,
+                },
+            };
+            map = map.map_put(Atom::from_str(env, name)?.encode(env), default.encode(env)?)?;
+        }
+        for &(key, value) in &self.effective {
+            map = map.map_put(key, value)?;
+        }
+        Ok(map)
+    }
+}
+
+// The value of an option that isn't given, RocksDB's own defaults.
+// Unset options don't change anything, e.g. there's no prefix extractor.
+enum DefaultValue {
+    Flag(bool),
+    Int(i64),
+    Atom(&'static str),
+    Unset,
+}
+
+impl DefaultValue {
+    fn encode<'a>(&self, env: Env<'a>) -> NifResult<Term<'a>> {
+        let value = match *self {
+            DefaultValue::Flag(flag) => flag.encode(env),
+            DefaultValue::Int(n) => n.encode(env),
+            DefaultValue::Atom(name) => Atom::from_str(env, name)?.encode(env),
+            DefaultValue::Unset => atoms::undefined().encode(env),
+        };
+        Ok(value)
+    }
+}
+
+// Every option of apply/4 with its default for the db and for a column
+// family, None if the scope doesn't take it. A column family without its
+// own value_codec uses the one of the db.
+const KNOWN: &[(&str, DefaultValue, Option<DefaultValue>)] = &[
+    ("key_codec", DefaultValue::Atom("raw"), None),
+    ("value_codec", DefaultValue::Atom("raw"), Some(DefaultValue::Unset)),
+    ("create_if_missing", DefaultValue::Flag(false), Some(DefaultValue::Flag(false))),
+    ("create_missing_column_families", DefaultValue::Flag(false), Some(DefaultValue::Flag(false))),
+    ("set_max_open_files", DefaultValue::Int(-1), Some(DefaultValue::Int(-1))),
+    ("set_use_fsync", DefaultValue::Flag(false), Some(DefaultValue::Flag(false))),
+    ("set_bytes_per_sync", DefaultValue::Int(0), Some(DefaultValue::Int(0))),
+    ("optimize_for_point_lookup", DefaultValue::Unset, Some(DefaultValue::Unset)),
+    ("set_table_cache_num_shard_bits", DefaultValue::Int(6), Some(DefaultValue::Int(6))),
+    ("set_max_write_buffer_number", DefaultValue::Int(2), Some(DefaultValue::Int(2))),
+    ("set_write_buffer_size", DefaultValue::Int(64 << 20), Some(DefaultValue::Int(64 << 20))),
+    ("set_target_file_size_base", DefaultValue::Int(64 << 20), Some(DefaultValue::Int(64 << 20))),
+    ("set_min_write_buffer_number_to_merge", DefaultValue::Int(1), Some(DefaultValue::Int(1))),
+    ("set_level_zero_stop_writes_trigger", DefaultValue::Int(36), Some(DefaultValue::Int(36))),
+    ("set_level_zero_slowdown_writes_trigger", DefaultValue::Int(20), Some(DefaultValue::Int(20))),
+    ("set_max_background_compactions", DefaultValue::Int(-1), Some(DefaultValue::Int(-1))),
+    ("set_max_background_flushes", DefaultValue::Int(-1), Some(DefaultValue::Int(-1))),
+    ("set_disable_auto_compactions", DefaultValue::Flag(false), Some(DefaultValue::Flag(false))),
+    ("set_compaction_style", DefaultValue::Atom("level"), Some(DefaultValue::Atom("level"))),
+    ("prefix_length", DefaultValue::Unset, Some(DefaultValue::Unset)),
+    ("wal_ttl_seconds", DefaultValue::Int(0), None),
+    ("wal_size_limit_mb", DefaultValue::Int(0), None),
+    ("resource_binary_threshold", DefaultValue::Unset, None),
+    ("event_listener", DefaultValue::Unset, None),
+];
+
+enum OptionError {
+    Unknown,
+    Invalid,
+    Immutable,
+}
+
+// Ok(Err(Term)) is {error, {unknown_option, Key}} or
+// {error, {invalid_option, Key, Value}}
+pub fn decode<'a>(env: Env<'a>, scope: Scope, term: Term<'a>) -> NifResult<Result<Decoded<'a>, Term<'a>>> {
+    let iter: MapIterator = term.decode()?;
+    let mut decoded = Decoded {
+        opts: Options::default(),
+        key_codec: KeyCodec::Raw,
+        value_codec: None,
+        binary_threshold: usize::MAX,
+        event_listener: None,
+        effective: Vec::new(),
+    };
+    for (key, value) in iter {
+        let result = match key.atom_to_string() {
+            Ok(name) => apply(&mut decoded, scope, &name, value),
+            Err(_) => Err(OptionError::Unknown),
+        };
+        match result {
+            Ok(()) => decoded.effective.push((key, value)),
+            Err(e) => return Ok(Err(option_error(env, e, key, value))),
+        }
+    }
+    Ok(Ok(decoded))
+}
+
+// Options of write_batch/3
+pub fn decode_write<'a>(env: Env<'a>, term: Term<'a>) -> NifResult<Result<WriteOptions, Term<'a>>> {
+    let iter: MapIterator = term.decode()?;
+    let mut opts = WriteOptions::default();
+    for (key, value) in iter {
+        let result = match key.atom_to_string() {
+            Ok(name) => apply_write(&mut opts, &name, value),
+            Err(_) => Err(OptionError::Unknown),
+        };
+        if let Err(e) = result {
+            return Ok(Err(option_error(env, e, key, value)));
+        }
+    }
+    Ok(Ok(opts))
+}
+
+// Options of set_options/2 that RocksDB changes on an open db, as names
+// and values of its options strings. {error, {immutable_option, Key}}
+// for the other options of open/2.
+pub fn decode_mutable<'a>(env: Env<'a>, term: Term<'a>) -> NifResult<Result<Vec<(&'static str, String)>, Term<'a>>> {
+    let iter: MapIterator = term.decode()?;
+    let mut opts = Vec::new();
+    for (key, value) in iter {
+        let result = match key.atom_to_string() {
+            Ok(name) => mutable(&name, value),
+            Err(_) => Err(OptionError::Unknown),
+        };
+        match result {
+            Ok(opt) => opts.push(opt),
+            Err(e) => return Ok(Err(option_error(env, e, key, value))),
+        }
+    }
+    Ok(Ok(opts))
+}
+
+fn option_error<'a>(env: Env<'a>, e: OptionError, key: Term<'a>, value: Term<'a>) -> Term<'a> {
+    match e {
+        OptionError::Unknown => errors::error(env, (atoms::unknown_option(), key)),
+        OptionError::Invalid => errors::error(env, (atoms::invalid_option(), key, value)),
+        OptionError::Immutable => errors::error(env, (atoms::immutable_option(), key)),
+    }
+}
+
+fn apply<'a>(decoded: &mut Decoded<'a>, scope: Scope, name: &str, value: Term<'a>) -> Result<(), OptionError> {
+    let opts = &mut decoded.opts;
+    match name {
+        "key_codec" if scope == Scope::Db => {
+            decoded.key_codec = KeyCodec::from_atom(&atom(value)?).ok_or(OptionError::Invalid)?;
+        }
+        "value_codec" => {
+            decoded.value_codec = Some(ValueCodec::from_atom(&atom(value)?).ok_or(OptionError::Invalid)?);
+        }
+        "create_if_missing" => opts.create_if_missing(flag(value)?),
+        "create_missing_column_families" => opts.create_missing_column_families(flag(value)?),
+        "set_max_open_files" => opts.set_max_open_files(number(value)?),
+        "set_use_fsync" => opts.set_use_fsync(flag(value)?),
+        "set_bytes_per_sync" => opts.set_bytes_per_sync(number(value)?),
+        "optimize_for_point_lookup" => opts.optimize_for_point_lookup(number(value)?),
+        "set_table_cache_num_shard_bits" => opts.set_table_cache_num_shard_bits(number(value)?),
+        "set_max_write_buffer_number" => opts.set_max_write_buffer_number(number(value)?),
+        "set_write_buffer_size" => opts.set_write_buffer_size(number(value)?),
+        "set_target_file_size_base" => opts.set_target_file_size_base(number(value)?),
+        "set_min_write_buffer_number_to_merge" => opts.set_min_write_buffer_number_to_merge(number(value)?),
+        "set_level_zero_stop_writes_trigger" => opts.set_level_zero_stop_writes_trigger(number(value)?),
+        "set_level_zero_slowdown_writes_trigger" => opts.set_level_zero_slowdown_writes_trigger(number(value)?),
+        "set_max_background_compactions" => opts.set_max_background_compactions(number(value)?),
+        "set_max_background_flushes" => opts.set_max_background_flushes(number(value)?),
+        "set_disable_auto_compactions" => opts.set_disable_auto_compactions(flag(value)?),
+        "set_compaction_style" => {
+            let style = match atom(value)?.as_str() {
+                "level" => DBCompactionStyle::Level,
+                "universal" => DBCompactionStyle::Universal,
+                "fifo" => DBCompactionStyle::Fifo,
+                _ => return Err(OptionError::Invalid),
+            };
+            opts.set_compaction_style(style);
+        }
+        "prefix_length" => {
+            let prefix_extractor = rocksdb::SliceTransform::create_fixed_prefix(number(value)?);
+            opts.set_prefix_extractor(prefix_extractor);
+        }
+        "wal_ttl_seconds" if scope == Scope::Db => opts.set_wal_ttl_seconds(number(value)?),
+        "wal_size_limit_mb" if scope == Scope::Db => opts.set_wal_size_limit_mb(number(value)?),
+        "resource_binary_threshold" if scope == Scope::Db => decoded.binary_threshold = number(value)?,
+        "event_listener" if scope == Scope::Db => {
+            decoded.event_listener = Some(value.decode().map_err(|_| OptionError::Invalid)?);
+        }
+        _ => return Err(OptionError::Unknown),
+    }
+    Ok(())
+}
+
+fn mutable(name: &str, value: Term) -> Result<(&'static str, String), OptionError> {
+    let opt = match name {
+        "set_write_buffer_size" => ("write_buffer_size", number::<u64>(value)?.to_string()),
+        "set_max_write_buffer_number" => ("max_write_buffer_number", number::<i32>(value)?.to_string()),
+        "set_target_file_size_base" => ("target_file_size_base", number::<u64>(value)?.to_string()),
+        "set_level_zero_stop_writes_trigger" => ("level0_stop_writes_trigger", number::<i32>(value)?.to_string()),
+        "set_level_zero_slowdown_writes_trigger" => ("level0_slowdown_writes_trigger", number::<i32>(value)?.to_string()),
+        "set_disable_auto_compactions" => ("disable_auto_compactions", flag(value)?.to_string()),
+        _ => {
+            // a valid option of open/2 is only fixed until the db is reopened
+            let mut decoded = Decoded {
+                opts: Options::default(),
+                key_codec: KeyCodec::Raw,
+                value_codec: None,
+                binary_threshold: usize::MAX,
+                event_listener: None,
+                effective: Vec::new(),
+            };
+            apply(&mut decoded, Scope::Db, name, value)?;
+            return Err(OptionError::Immutable);
+        }
+    };
+    Ok(opt)
+}
+
+fn apply_write(opts: &mut WriteOptions, name: &str, value: Term) -> Result<(), OptionError> {
+    match name {
+        "sync" => opts.set_sync(flag(value)?),
+        "disable_wal" => opts.disable_wal(flag(value)?),
+        _ => return Err(OptionError::Unknown),
+    }
+    Ok(())
+}
+
+fn atom(value: Term) -> Result<String, OptionError> {
+    value.atom_to_string().map_err(|_| OptionError::Invalid)
+}
+
+fn flag(value: Term) -> Result<bool, OptionError> {
+    match atom(value)?.as_str() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(OptionError::Invalid),
+    }
+}
+
+fn number<'a, T: Decoder<'a>>(value: Term<'a>) -> Result<T, OptionError> {
+    value.decode().map_err(|_| OptionError::Invalid)
+}
@@ -0,0 +1,90 @@
+// The bindings have no GetApproximateSizes or GetLiveFilesMetaData, the
+// files they look at are listed by the "rocksdb.sstables" property.
+// RocksDB 5.14 prints it with Version::DebugString(hex, print_stats), a
+// line per level and file:
+//
+//   --- level 1 --- version# 3 ---
+//    17:4096['6B31' seq:5, type:1 .. '6B39' seq:9, type:1](0)
+//
+// that is number:size[smallest .. largest](reads), the user keys in hex.
+pub const PROPERTY: &str = "rocksdb.sstables";
+
+pub struct SstFile {
+    pub level: usize,
+    pub number: u64,
+    pub size: u64,
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+}
+
+impl SstFile {
+    // as RocksDB names it in the db directory
+    pub fn name(&self) -> String {
+        format!("/{:06}.sst", self.number)
+    }
+
+    // with a key in [start, end)
+    pub fn overlaps(&self, start: &[u8], end: &[u8]) -> bool {
+        self.smallest.as_slice() < end && self.largest.as_slice() >= start
+    }
+}
+
+pub fn parse(listing: &str) -> Result<Vec<SstFile>, String> {
+    let mut files = Vec::new();
+    let mut level = None;
+    for line in listing.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with("--- level ") {
+            let number = line.split_whitespace().nth(2).unwrap_or("");
+            level = Some(number.parse::<usize>().map_err(|_| malformed(line))?);
+            continue;
+        }
+        let level = level.ok_or_else(|| malformed(line))?;
+        files.push(parse_file(level, line).ok_or_else(|| malformed(line))?);
+    }
+    Ok(files)
+}
+
+fn parse_file(level: usize, line: &str) -> Option<SstFile> {
+    let colon = line.find(':')?;
+    let open = line.find('[')?;
+    let close = line.rfind(']')?;
+    if !(colon < open && open < close) {
+        return None;
+    }
+    let keys = &line[open + 1..close];
+    let separator = keys.find(" .. '")?;
+    Some(SstFile {
+        level,
+        number: line[..colon].parse().ok()?,
+        size: line[colon + 1..open].parse().ok()?,
+        smallest: parse_key(&keys[..separator])?,
+        largest: parse_key(&keys[separator + " .. ".len()..])?,
+    })
+}
+
+// 'HEX' seq:N, type:N
+fn parse_key(key: &str) -> Option<Vec<u8>> {
+    if !key.starts_with('\'') {
+        return None;
+    }
+    let hex = &key[1..];
+    let hex = &hex[..hex.find('\'')?];
+    hex.as_bytes().chunks(2)
+        .map(|pair| match *pair {
+            [high, low] => Some(digit(high)? << 4 | digit(low)?),
+            _ => None,
+        })
+        .collect()
+}
+
+fn digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+fn malformed(line: &str) -> String {
+    format!("unexpected line in {}: {}", PROPERTY, line)
+}
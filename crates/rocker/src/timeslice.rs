@@ -0,0 +1,45 @@
+use rustler::Env;
+use rustler::schedule::consume_timeslice;
+use std::time::Instant;
+
+// Operations between two reports to the scheduler
+const CHECK_EVERY: usize = 64;
+
+// A timeslice of a normal scheduler is about a millisecond.
+const SLICE_MICROS: u128 = 1000;
+
+// Reports the time spent in a long running NIF to the scheduler, the NIF
+// returns the rest of its work once the timeslice of the process is used.
+pub struct Timeslice<'a> {
+    env: Env<'a>,
+    start: Instant,
+    left: usize,
+}
+
+impl<'a> Timeslice<'a> {
+    pub fn new(env: Env<'a>) -> Timeslice<'a> {
+        Timeslice {
+            env,
+            start: Instant::now(),
+            left: CHECK_EVERY,
+        }
+    }
+
+    // true when the caller should yield
+    pub fn tick(&mut self) -> bool {
+        self.left -= 1;
+        if self.left > 0 {
+            return false;
+        }
+        self.left = CHECK_EVERY;
+        let now = Instant::now();
+        let micros = now.duration_since(self.start).as_micros();
+        self.start = now;
+        let percent = match micros * 100 / SLICE_MICROS {
+            0 => 1,
+            p if p > 100 => 100,
+            p => p,
+        };
+        consume_timeslice(self.env, percent as i32)
+    }
+}
@@ -0,0 +1,85 @@
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use rustler::{Encoder, Env, NifResult, Term};
+use rustler::types::binary::{Binary, OwnedBinary};
+use std::borrow::Cow;
+use std::io::Write;
+
+// Erlang external term format markers
+const VERSION_MAGIC: u8 = 131;
+const COMPRESSED: u8 = 80;
+
+// Terms smaller than this are stored uncompressed by `term_compressed`,
+// binary_to_term accepts both forms.
+const COMPRESS_THRESHOLD: usize = 64;
+
+const ZSTD_LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ValueCodec {
+    Raw,
+    Term,
+    TermCompressed,
+    Zstd,
+}
+
+impl ValueCodec {
+    pub fn from_atom(name: &str) -> Option<ValueCodec> {
+        match name {
+            "raw" => Some(ValueCodec::Raw),
+            "term" => Some(ValueCodec::Term),
+            "term_compressed" => Some(ValueCodec::TermCompressed),
+            "zstd" => Some(ValueCodec::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn encode<'a>(&self, term: Term<'a>) -> NifResult<Cow<'a, [u8]>> {
+        match *self {
+            ValueCodec::Raw => {
+                let bin: Binary<'a> = term.decode()?;
+                Ok(Cow::Borrowed(bin.as_slice()))
+            }
+            ValueCodec::Term => Ok(Cow::Owned(term.to_binary().to_vec())),
+            ValueCodec::TermCompressed => {
+                let raw = term.to_binary();
+                if raw.len() < COMPRESS_THRESHOLD {
+                    return Ok(Cow::Owned(raw.to_vec()));
+                }
+                let mut out = Vec::with_capacity(raw.len() / 2);
+                out.push(VERSION_MAGIC);
+                out.push(COMPRESSED);
+                out.extend_from_slice(&((raw.len() - 1) as u32).to_be_bytes());
+                let mut encoder = ZlibEncoder::new(out, Compression::default());
+                encoder.write_all(&raw[1..]).unwrap();
+                Ok(Cow::Owned(encoder.finish().unwrap()))
+            }
+            ValueCodec::Zstd => {
+                let raw = term.to_binary();
+                Ok(Cow::Owned(zstd::encode_all(&raw[..], ZSTD_LEVEL).unwrap()))
+            }
+        }
+    }
+
+    pub fn decode<'a>(&self, env: Env<'a>, bytes: &[u8]) -> Result<Term<'a>, String> {
+        match *self {
+            ValueCodec::Raw => {
+                let mut bin = OwnedBinary::new(bytes.len()).unwrap();
+                bin.clone_from_slice(bytes);
+                Ok(bin.release(env).encode(env))
+            }
+            ValueCodec::Term | ValueCodec::TermCompressed => binary_to_term(env, bytes),
+            ValueCodec::Zstd => {
+                let raw = zstd::decode_all(bytes).map_err(|e| e.to_string())?;
+                binary_to_term(env, &raw)
+            }
+        }
+    }
+}
+
+fn binary_to_term<'a>(env: Env<'a>, bytes: &[u8]) -> Result<Term<'a>, String> {
+    match env.binary_to_term(bytes) {
+        Some((term, _)) => Ok(term),
+        None => Err("invalid external term format".to_string()),
+    }
+}
@@ -0,0 +1,177 @@
+// Decoder for the serialized WriteBatch representation (db/write_batch.cc):
+// fixed64 sequence, fixed32 count, then `count` tagged records.
+
+const HEADER_SIZE: usize = 12;
+
+const TYPE_DELETION: u8 = 0x0;
+const TYPE_VALUE: u8 = 0x1;
+const TYPE_MERGE: u8 = 0x2;
+const TYPE_LOG_DATA: u8 = 0x3;
+const TYPE_CF_DELETION: u8 = 0x4;
+const TYPE_CF_VALUE: u8 = 0x5;
+const TYPE_CF_MERGE: u8 = 0x6;
+const TYPE_SINGLE_DELETION: u8 = 0x7;
+const TYPE_CF_SINGLE_DELETION: u8 = 0x8;
+const TYPE_BEGIN_PREPARE_XID: u8 = 0x9;
+const TYPE_END_PREPARE_XID: u8 = 0xA;
+const TYPE_COMMIT_XID: u8 = 0xB;
+const TYPE_ROLLBACK_XID: u8 = 0xC;
+const TYPE_NOOP: u8 = 0xD;
+const TYPE_CF_RANGE_DELETION: u8 = 0xE;
+const TYPE_RANGE_DELETION: u8 = 0xF;
+const TYPE_BEGIN_PERSISTED_PREPARE_XID: u8 = 0x12;
+const TYPE_BEGIN_UNPREPARE_XID: u8 = 0x13;
+
+pub enum BatchOp<'a> {
+    Put(u32, &'a [u8], &'a [u8]),
+    Delete(u32, &'a [u8]),
+    Merge(u32, &'a [u8], &'a [u8]),
+    DeleteRange(u32, &'a [u8], &'a [u8]),
+}
+
+pub fn count(data: &[u8]) -> Result<u32, String> {
+    if data.len() < HEADER_SIZE {
+        return Err("malformed write batch header".to_string());
+    }
+    let mut count = [0u8; 4];
+    count.copy_from_slice(&data[8..12]);
+    Ok(u32::from_le_bytes(count))
+}
+
+pub fn parse(data: &[u8]) -> Result<Vec<BatchOp<'_>>, String> {
+    let count = count(data)? as usize;
+
+    let mut reader = Reader { data, pos: HEADER_SIZE };
+    let mut ops = Vec::with_capacity(count);
+    while reader.pos < data.len() {
+        let tag = reader.byte()?;
+        let op = match tag {
+            TYPE_VALUE => BatchOp::Put(0, reader.slice()?, reader.slice()?),
+            TYPE_CF_VALUE => {
+                let cf = reader.varint32()?;
+                BatchOp::Put(cf, reader.slice()?, reader.slice()?)
+            }
+            TYPE_DELETION | TYPE_SINGLE_DELETION => BatchOp::Delete(0, reader.slice()?),
+            TYPE_CF_DELETION | TYPE_CF_SINGLE_DELETION => {
+                let cf = reader.varint32()?;
+                BatchOp::Delete(cf, reader.slice()?)
+            }
+            TYPE_MERGE => BatchOp::Merge(0, reader.slice()?, reader.slice()?),
+            TYPE_CF_MERGE => {
+                let cf = reader.varint32()?;
+                BatchOp::Merge(cf, reader.slice()?, reader.slice()?)
+            }
+            TYPE_RANGE_DELETION => BatchOp::DeleteRange(0, reader.slice()?, reader.slice()?),
+            TYPE_CF_RANGE_DELETION => {
+                let cf = reader.varint32()?;
+                BatchOp::DeleteRange(cf, reader.slice()?, reader.slice()?)
+            }
+            // markers and blobs that don't count as operations
+            TYPE_LOG_DATA | TYPE_END_PREPARE_XID | TYPE_COMMIT_XID | TYPE_ROLLBACK_XID => {
+                reader.slice()?;
+                continue;
+            }
+            TYPE_BEGIN_PREPARE_XID | TYPE_BEGIN_PERSISTED_PREPARE_XID
+            | TYPE_BEGIN_UNPREPARE_XID | TYPE_NOOP => continue,
+            _ => return Err(format!("unknown write batch tag {}", tag)),
+        };
+        ops.push(op);
+    }
+    if ops.len() != count {
+        return Err("write batch count mismatch".to_string());
+    }
+
+    Ok(ops)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, String> {
+        match self.data.get(self.pos) {
+            Some(b) => {
+                self.pos += 1;
+                Ok(*b)
+            }
+            None => Err("truncated write batch".to_string()),
+        }
+    }
+
+    fn varint32(&mut self) -> Result<u32, String> {
+        let mut result: u32 = 0;
+        let mut shift = 0;
+        while shift <= 28 {
+            let b = self.byte()?;
+            result |= u32::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+        Err("malformed varint in write batch".to_string())
+    }
+
+    fn slice(&mut self) -> Result<&'a [u8], String> {
+        let len = self.varint32()? as usize;
+        if self.data.len() < self.pos + len {
+            return Err("truncated write batch".to_string());
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+}
+
+pub fn serialize(ops: &[BatchOp]) -> Vec<u8> {
+    let mut out = vec![0u8; 8];
+    out.extend_from_slice(&(ops.len() as u32).to_le_bytes());
+    for op in ops {
+        match *op {
+            BatchOp::Put(cf, key, value) => {
+                put_tag(&mut out, cf, TYPE_VALUE, TYPE_CF_VALUE);
+                put_slice(&mut out, key);
+                put_slice(&mut out, value);
+            }
+            BatchOp::Delete(cf, key) => {
+                put_tag(&mut out, cf, TYPE_DELETION, TYPE_CF_DELETION);
+                put_slice(&mut out, key);
+            }
+            BatchOp::Merge(cf, key, value) => {
+                put_tag(&mut out, cf, TYPE_MERGE, TYPE_CF_MERGE);
+                put_slice(&mut out, key);
+                put_slice(&mut out, value);
+            }
+            BatchOp::DeleteRange(cf, start, end) => {
+                put_tag(&mut out, cf, TYPE_RANGE_DELETION, TYPE_CF_RANGE_DELETION);
+                put_slice(&mut out, start);
+                put_slice(&mut out, end);
+            }
+        }
+    }
+    out
+}
+
+fn put_tag(out: &mut Vec<u8>, cf: u32, default_tag: u8, cf_tag: u8) {
+    if cf == 0 {
+        out.push(default_tag);
+    } else {
+        out.push(cf_tag);
+        put_varint32(out, cf);
+    }
+}
+
+fn put_varint32(out: &mut Vec<u8>, mut v: u32) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn put_slice(out: &mut Vec<u8>, slice: &[u8]) {
+    put_varint32(out, slice.len() as u32);
+    out.extend_from_slice(slice);
+}
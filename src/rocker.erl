@@ -5,6 +5,7 @@
          open/2,
          open_default/1,
          open_cf_default/2,
+         open_cf/3,
          destroy/1,
          repair/1,
          path/1,
@@ -23,8 +24,67 @@
          put_cf/4,
          get_cf/3,
          delete_cf/3,
+         put_if_absent/3,
+         compare_and_swap/4,
+         delete_if_equals/3,
+         put_if_absent_cf/4,
+         compare_and_swap_cf/5,
+         delete_if_equals_cf/4,
+         incr/3,
+         incr_cf/4,
+         counter_get/2,
+         estimate_num_keys/1,
+         estimate_num_keys_cf/2,
+         approximate_sizes/3,
+         approximate_sizes_cf/4,
+         live_files/1,
+         column_family_metadata/2,
+         set_options/2,
          iterator_cf/3,
-         prefix_iterator_cf/3
+         prefix_iterator_cf/3,
+         key_encode/1,
+         key_decode/1,
+         multi_get/2,
+         latest_sequence_number/1,
+         get_updates_since/2,
+         wal_next/1,
+         write_batch_data/1,
+         apply_batch_data/3,
+         applied_sequence_number/1,
+         get_async/3,
+         put_async/4,
+         tx_async/3,
+         multi_get_async/3,
+         effective_options/2,
+         close/1,
+         close/2,
+         released/1,
+         cf_handle/2,
+         batch_new/0,
+         batch_put/3,
+         batch_put_cf/4,
+         batch_delete/2,
+         batch_delete_cf/3,
+         batch_merge/3,
+         batch_delete_range/3,
+         batch_count/1,
+         batch_data_size/1,
+         batch_clear/1,
+         batch_set_savepoint/1,
+         batch_rollback_to_savepoint/1,
+         batch_pop_savepoint/1,
+         tx_set_savepoint/1,
+         tx_rollback_to_savepoint/1,
+         tx_pop_savepoint/1,
+         write_batch/3,
+         wbwi_new/0,
+         wbwi_get/3,
+         wbwi_iterator/3,
+         tx_set_name/2,
+         tx_prepare/2,
+         tx_commit/2,
+         tx_rollback/2,
+         get_all_prepared_transactions/1
          ]).
 
 %% Native library support
@@ -47,6 +107,9 @@ open_default(_Path) ->
 open_cf_default(_Path, _Cfs) ->
     not_loaded(?LINE).
 
+open_cf(_Path, _Options, _CfOptions) ->
+    not_loaded(?LINE).
+
 destroy(_Path) ->
     not_loaded(?LINE).
 
@@ -65,8 +128,16 @@ get(_Db, _Key) ->
 delete(_Db, _Key) ->
     not_loaded(?LINE).
 
-tx(_Db, _Operations) ->
-    not_loaded(?LINE).
+tx(Db, Operations) ->
+    tx(Db, Operations, undefined).
+
+tx(Db, Operations, Pending) ->
+    case tx_slice(Db, Operations, Pending) of
+        {continue, Pending2, Rest} ->
+            tx(Db, Rest, Pending2);
+        Result ->
+            Result
+    end.
 
 iterator(_Db, _Mode) ->
     not_loaded(?LINE).
@@ -101,19 +172,220 @@ get_cf(_Db, _Cf, _Key) ->
 delete_cf(_Db, _Cf, _Key) ->
     not_loaded(?LINE).
 
+put_if_absent(_Db, _Key, _Value) ->
+    not_loaded(?LINE).
+
+compare_and_swap(_Db, _Key, _Expected, _Value) ->
+    not_loaded(?LINE).
+
+delete_if_equals(_Db, _Key, _Expected) ->
+    not_loaded(?LINE).
+
+put_if_absent_cf(_Db, _Cf, _Key, _Value) ->
+    not_loaded(?LINE).
+
+compare_and_swap_cf(_Db, _Cf, _Key, _Expected, _Value) ->
+    not_loaded(?LINE).
+
+delete_if_equals_cf(_Db, _Cf, _Key, _Expected) ->
+    not_loaded(?LINE).
+
+incr(_Db, _Key, _Delta) ->
+    not_loaded(?LINE).
+
+incr_cf(_Db, _Cf, _Key, _Delta) ->
+    not_loaded(?LINE).
+
+counter_get(_Db, _Key) ->
+    not_loaded(?LINE).
+
+estimate_num_keys(_Db) ->
+    not_loaded(?LINE).
+
+estimate_num_keys_cf(_Db, _Cf) ->
+    not_loaded(?LINE).
+
+approximate_sizes(_Db, _Ranges, _Options) ->
+    not_loaded(?LINE).
+
+approximate_sizes_cf(_Db, _Cf, _Ranges, _Options) ->
+    not_loaded(?LINE).
+
+live_files(_Db) ->
+    not_loaded(?LINE).
+
+column_family_metadata(_Db, _Cf) ->
+    not_loaded(?LINE).
+
+set_options(_Db, _Options) ->
+    not_loaded(?LINE).
+
 iterator_cf(_Db, _Cf, _Mode) ->
     not_loaded(?LINE).
 
 prefix_iterator_cf(_Db, _Cf, _Prefix) ->
     not_loaded(?LINE).
 
+key_encode(_Term) ->
+    not_loaded(?LINE).
+
+key_decode(_Key) ->
+    not_loaded(?LINE).
+
+multi_get(Db, Keys) ->
+    multi_get(Db, Keys, []).
+
+multi_get(Db, Keys, Acc) ->
+    case multi_get_slice(Db, Keys, Acc) of
+        {continue, Acc2, Rest} ->
+            multi_get(Db, Rest, Acc2);
+        {ok, Results} ->
+            {ok, lists:reverse(Results)};
+        Error ->
+            Error
+    end.
+
+latest_sequence_number(_Db) ->
+    not_loaded(?LINE).
+
+get_updates_since(_Db, _SeqNo) ->
+    not_loaded(?LINE).
+
+wal_next(_WalIter) ->
+    not_loaded(?LINE).
+
+write_batch_data(_Ops) ->
+    not_loaded(?LINE).
+
+apply_batch_data(_Db, _SeqNo, _Data) ->
+    not_loaded(?LINE).
+
+applied_sequence_number(_Db) ->
+    not_loaded(?LINE).
+
+get_async(_Db, _Key, _Ref) ->
+    not_loaded(?LINE).
+
+put_async(_Db, _Key, _Value, _Ref) ->
+    not_loaded(?LINE).
+
+tx_async(_Db, _Txn, _Ref) ->
+    not_loaded(?LINE).
+
+multi_get_async(_Db, _Keys, _Ref) ->
+    not_loaded(?LINE).
+
+effective_options(_Scope, _Options) ->
+    not_loaded(?LINE).
+
+close(_Db) ->
+    not_loaded(?LINE).
+
+close(_Db, _Options) ->
+    not_loaded(?LINE).
+
+released(_Db) ->
+    not_loaded(?LINE).
+
+cf_handle(_Db, _Name) ->
+    not_loaded(?LINE).
+
+batch_new() ->
+    not_loaded(?LINE).
+
+batch_put(_Batch, _Key, _Value) ->
+    not_loaded(?LINE).
+
+batch_put_cf(_Batch, _Cf, _Key, _Value) ->
+    not_loaded(?LINE).
+
+batch_delete(_Batch, _Key) ->
+    not_loaded(?LINE).
+
+batch_delete_cf(_Batch, _Cf, _Key) ->
+    not_loaded(?LINE).
+
+batch_merge(_Batch, _Key, _Value) ->
+    not_loaded(?LINE).
+
+batch_delete_range(_Batch, _From, _To) ->
+    not_loaded(?LINE).
+
+batch_count(_Batch) ->
+    not_loaded(?LINE).
+
+batch_data_size(_Batch) ->
+    not_loaded(?LINE).
+
+batch_clear(_Batch) ->
+    not_loaded(?LINE).
+
+batch_set_savepoint(_Batch) ->
+    not_loaded(?LINE).
+
+batch_rollback_to_savepoint(_Batch) ->
+    not_loaded(?LINE).
+
+batch_pop_savepoint(_Batch) ->
+    not_loaded(?LINE).
+
+%% Savepoints of a transaction built in a batch
+tx_set_savepoint(Batch) ->
+    batch_set_savepoint(Batch).
+
+tx_rollback_to_savepoint(Batch) ->
+    batch_rollback_to_savepoint(Batch).
+
+tx_pop_savepoint(Batch) ->
+    batch_pop_savepoint(Batch).
+
+write_batch(_Db, _Batch, _WriteOpts) ->
+    not_loaded(?LINE).
+
+wbwi_new() ->
+    not_loaded(?LINE).
+
+wbwi_get(_Batch, _Db, _Key) ->
+    not_loaded(?LINE).
+
+wbwi_iterator(_Batch, _Db, _Mode) ->
+    not_loaded(?LINE).
+
+%% Two-phase commit of a named batch
+tx_set_name(_Batch, _Name) ->
+    not_loaded(?LINE).
+
+tx_prepare(_Db, _Batch) ->
+    not_loaded(?LINE).
+
+tx_commit(Db, Batch) ->
+    write_batch(Db, Batch, #{sync => true}).
+
+tx_rollback(_Db, _Batch) ->
+    not_loaded(?LINE).
+
+get_all_prepared_transactions(_Db) ->
+    not_loaded(?LINE).
+
+%% Long lists are processed a timeslice at a time, the NIFs return
+%% {continue, State, Rest} to be called again after yielding.
+tx_slice(_Db, _Operations, _Pending) ->
+    not_loaded(?LINE).
+
+multi_get_slice(_Db, _Keys, _Acc) ->
+    not_loaded(?LINE).
+
 
 %%==============================================================================
 %% helpers
 %%==============================================================================
 
 load() ->
-    erlang:load_nif(filename:join(priv(), "librocker"), none).
+    AsyncThreads = application:get_env(rocker, async_threads, 4),
+    ErrorFormat = application:get_env(rocker, error_format, structured),
+    erlang:load_nif(filename:join(priv(), "librocker"),
+                    #{async_threads => AsyncThreads,
+                      error_format => ErrorFormat}).
 
 not_loaded(Line) ->
     erlang:nif_error({error, {not_loaded, [{module, ?MODULE}, {line, Line}]}}).
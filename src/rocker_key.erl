@@ -0,0 +1,19 @@
+-module(rocker_key).
+
+%% API
+-export([encode/1,
+         decode/1
+         ]).
+
+%%==============================================================================
+%% api
+%%==============================================================================
+
+%% Encodes integers, floats, atoms, binaries, tuples and proper lists into a
+%% binary whose byte order matches the Erlang term order of the source terms.
+%% Tuples and lists can be nested 100 levels deep.
+encode(Term) ->
+    rocker:key_encode(Term).
+
+decode(Key) ->
+    rocker:key_decode(Key).
@@ -10,6 +10,15 @@ all() ->
     {group, atomic},
     {group, iterator},
     {group, cf},
+    {group, key_codec},
+    {group, value_codec},
+    {group, wal},
+    {group, async},
+    {group, errors},
+    {group, close},
+    {group, pinned},
+    {group, batch},
+    {group, stats},
     {group, perf}
   ].
 
@@ -17,17 +26,24 @@ groups() ->
   [
     {init,
       [parallel, shuffle],
-      [lxcode, open, open_default, destroy, repair, path, open_multi_ptr]},
+      [lxcode, open, open_default, destroy, repair, path, open_multi_ptr,
+        open_unknown_option, open_invalid_option, create_cf_options,
+        effective_options, set_options, set_options_errors,
+        bulk_load_cf]},
 
     {atomic,
       [parallel, shuffle],
-      [put_get, put_get_bin, delete, write_batch]},
+      [put_get, put_get_bin, delete, write_batch, write_batch_large,
+        write_batch_large_error, write_batch_bad_op, put_if_absent,
+        compare_and_swap, compare_and_swap_term, compare_and_swap_cf,
+        compare_and_swap_concurrent, incr, incr_cf, incr_concurrent,
+        incr_tx]},
 
     {iterator,
       [parallel, shuffle],
       [create_iterator, next_start, next_end,
         next_from_forward, next_from_reverse,
-        prefix_iterator]},
+        prefix_iterator, iterator_keeps_db, iterator_cf_dropped]},
 
     {cf,
       [parallel, shuffle],
@@ -35,7 +51,61 @@ groups() ->
         put_cf_get_cf, put_cf_get_cf_multi, delete_cf,
         create_iterator_cf, create_iterator_cf_not_found_cf, next_end_cf,
         next_from_forward_cf, next_from_reverse_cf,
-        prefix_iterator_cf, write_batch_cf]},
+        prefix_iterator_cf, write_batch_cf,
+        unknown_cf_ops, unknown_cf_tx, open_cf_default_missing_cf,
+        cf_handle, cf_handle_dropped, cf_handle_other_db]},
+
+    {key_codec,
+      [parallel, shuffle],
+      [key_encode_decode, key_encode_order, key_encode_badarg,
+        key_codec_put_get, key_codec_iterator, key_codec_tx,
+        key_codec_reopen_cf]},
+
+    {value_codec,
+      [parallel, shuffle],
+      [value_codec_raw, value_codec_term, value_codec_term_compressed,
+        value_codec_zstd, value_codec_cf, value_codec_iterator,
+        value_codec_tx, multi_get, multi_get_large]},
+
+    {wal,
+      [parallel, shuffle],
+      [latest_sequence_number, get_updates_since, get_updates_since_cf_ops,
+        write_batch_data, apply_batch_data, apply_batch_data_order,
+        replicate_follower]},
+
+    {async,
+      [parallel, shuffle],
+      [get_async, put_async, tx_async, tx_async_no_partial,
+        multi_get_async, async_badarg, async_many]},
+
+    {errors,
+      [parallel, shuffle],
+      [error_io, error_invalid_argument, error_corruption, error_legacy]},
+
+    {close,
+      [parallel, shuffle],
+      [close_reopen, close_ops, close_iterators, close_flush, close_async]},
+
+    {pinned,
+      [parallel, shuffle],
+      [pinned_get, pinned_get_cf, pinned_next, pinned_small,
+        pinned_multi_get, pinned_close]},
+
+    {batch,
+      [parallel, shuffle],
+      [batch_write, batch_cf, batch_savepoint, batch_write_opts,
+        batch_consumed, batch_other_db, batch_codecs, wbwi_get,
+        wbwi_iterator,
+        wbwi_iterator_modes, wbwi_savepoint, wbwi_unsupported,
+        tx_savepoint, tx_savepoint_nested, tx_pop_savepoint,
+        tx_prepare_commit, tx_prepare_recover, tx_prepare_errors]},
+
+    {stats,
+      [parallel, shuffle],
+      [estimate_num_keys, estimate_num_keys_cf, approximate_sizes,
+        approximate_sizes_cf, column_family_metadata,
+        column_family_metadata_cf, live_files, event_listener_flush,
+        event_listener_stall]},
 
     {perf,
       [shuffle],
@@ -104,6 +174,75 @@ open_multi_ptr(_) ->
   true = is_reference(Db3),
   ok.
 
+open_unknown_option(_) ->
+  Path = <<"/project/priv/db_open_unknown_option">>,
+  rocker:destroy(Path),
+  {error, {unknown_option, create_if_mising}} = rocker:open(Path, #{
+    create_if_mising => true
+  }),
+  {error, {unknown_option, <<"create_if_missing">>}} = rocker:open(Path, #{
+    <<"create_if_missing">> => true
+  }),
+  ok.
+
+open_invalid_option(_) ->
+  Path = <<"/project/priv/db_open_invalid_option">>,
+  rocker:destroy(Path),
+  {error, {invalid_option, create_if_missing, yes}} = rocker:open(Path, #{
+    create_if_missing => yes
+  }),
+  {error, {invalid_option, set_max_open_files, <<"1000">>}} =
+    rocker:open(Path, #{set_max_open_files => <<"1000">>}),
+  {error, {invalid_option, set_compaction_style, lsm}} =
+    rocker:open(Path, #{set_compaction_style => lsm}),
+  {error, {invalid_option, value_codec, json}} =
+    rocker:open(Path, #{value_codec => json}),
+  {error, {invalid_option, event_listener, listener}} =
+    rocker:open(Path, #{event_listener => listener}),
+  ok.
+
+create_cf_options(_) ->
+  Path = <<"/project/priv/db_create_cf_options">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    set_use_fsync => false
+  }),
+  {error, {unknown_option, key_codec}} =
+    rocker:create_cf(Db, <<"testcf">>, #{key_codec => sortable_term}),
+  {error, {unknown_option, wal_ttl_seconds}} =
+    rocker:create_cf(Db, <<"testcf">>, #{wal_ttl_seconds => 60}),
+  ok = rocker:create_cf(Db, <<"testcf">>, #{
+    set_disable_auto_compactions => false,
+    set_compaction_style => level
+  }),
+  ok.
+
+effective_options(_) ->
+  Opts = #{
+    create_if_missing => true,
+    set_use_fsync => false,
+    set_max_open_files => 1000,
+    key_codec => sortable_term
+  },
+  {ok, Db} = rocker:effective_options(db, Opts),
+  Opts = maps:with(maps:keys(Opts), Db),
+  #{set_write_buffer_size := 67108864, set_disable_auto_compactions := false,
+    set_compaction_style := level, prefix_length := undefined,
+    wal_ttl_seconds := 0, value_codec := raw} = Db,
+  {ok, Defaults} = rocker:effective_options(db, #{}),
+  #{create_if_missing := false, key_codec := raw} = Defaults,
+  true = maps:keys(Db) =:= maps:keys(Defaults),
+  {ok, Cf} = rocker:effective_options(cf, #{}),
+  #{set_max_write_buffer_number := 2, value_codec := undefined} = Cf,
+  false = lists:any(fun(K) -> maps:is_key(K, Cf) end,
+    [key_codec, wal_ttl_seconds, wal_size_limit_mb,
+      resource_binary_threshold, event_listener]),
+  {error, {unknown_option, key_codec}} =
+    rocker:effective_options(cf, Opts),
+  {'EXIT', {badarg, _}} = (catch rocker:effective_options(table, #{})),
+  ok.
+
 destroy(_) ->
   Path = <<"/project/priv/db_destr">>,
   Test = self(),
@@ -138,6 +277,92 @@ path(_) ->
   {ok, Path} = rocker:path(Db),
   ok.
 
+set_options(_) ->
+  Path = <<"/project/priv/db_set_options">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:set_options(Db, #{
+    set_disable_auto_compactions => true,
+    set_level_zero_slowdown_writes_trigger => 100,
+    set_level_zero_stop_writes_trigger => 120,
+    set_write_buffer_size => 64 * 1024
+  }),
+  %% small memtables flush into many L0 files, more than the 4 that
+  %% start a compaction, and they stay there until it's enabled again
+  V = binary:copy(<<"v">>, 1024),
+  [ok = rocker:put(Db, <<N:32>>, V) || N <- lists:seq(1, 1000)],
+  true = wait_until(fun() -> level0_files(Db) > 8 end, 100),
+  timer:sleep(500),
+  true = level0_files(Db) > 8,
+  ok = rocker:set_options(Db, #{set_disable_auto_compactions => false}),
+  ok = rocker:put(Db, <<"k">>, V),
+  true = wait_until(fun() -> level0_files(Db) < 4 end, 100),
+  ok = rocker:set_options(Db, #{}),
+  {ok, V} = rocker:get(Db, <<1:32>>),
+  ok.
+
+level0_files(Db) ->
+  level0_files(Db, default).
+
+level0_files(Db, Cf) ->
+  {ok, #{levels := [#{level := 0, files := Files} | _]}} =
+    rocker:column_family_metadata(Db, Cf),
+  Files.
+
+%% the options of a named cf change with a reopen only
+bulk_load_cf(_) ->
+  Path = <<"/project/priv/db_bulk_load_cf">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  Opts = #{
+    set_level_zero_slowdown_writes_trigger => 100,
+    set_level_zero_stop_writes_trigger => 120,
+    set_write_buffer_size => 64 * 1024
+  },
+  ok = rocker:create_cf(Db, <<"bulk">>,
+    Opts#{set_disable_auto_compactions => true}),
+  V = binary:copy(<<"v">>, 1024),
+  [ok = rocker:put_cf(Db, <<"bulk">>, <<N:32>>, V) || N <- lists:seq(1, 1000)],
+  true = wait_until(fun() -> level0_files(Db, <<"bulk">>) > 8 end, 100),
+  timer:sleep(500),
+  true = level0_files(Db, <<"bulk">>) > 8,
+  ok = rocker:close(Db),
+  {ok, Db2} = rocker:open_cf(Path, #{}, #{
+    <<"bulk">> => Opts#{set_disable_auto_compactions => false}
+  }),
+  ok = rocker:put_cf(Db2, <<"bulk">>, <<"k">>, V),
+  true = wait_until(fun() -> level0_files(Db2, <<"bulk">>) < 4 end, 100),
+  {ok, V} = rocker:get_cf(Db2, <<"bulk">>, <<1:32>>),
+  ok.
+
+wait_until(Fun, 0) ->
+  Fun();
+wait_until(Fun, N) ->
+  case Fun() of
+    true -> true;
+    false ->
+      timer:sleep(100),
+      wait_until(Fun, N - 1)
+  end.
+
+set_options_errors(_) ->
+  Path = <<"/project/priv/db_set_options_errors">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {error, {immutable_option, create_if_missing}} =
+    rocker:set_options(Db, #{create_if_missing => true}),
+  {error, {immutable_option, prefix_length}} =
+    rocker:set_options(Db, #{prefix_length => 3}),
+  {error, {unknown_option, foo}} = rocker:set_options(Db, #{foo => 1}),
+  {error, {invalid_option, set_disable_auto_compactions, yes}} =
+    rocker:set_options(Db, #{set_disable_auto_compactions => yes}),
+  {error, {invalid_option, set_write_buffer_size, -1}} =
+    rocker:set_options(Db, #{set_write_buffer_size => -1}),
+  ok = rocker:close(Db),
+  {error, closed} =
+    rocker:set_options(Db, #{set_disable_auto_compactions => true}),
+  ok.
+
 %% =============================================================================
 %% group: atomic
 %% =============================================================================
@@ -172,6 +397,173 @@ delete(_) ->
   notfound = rocker:get(Db, <<"key">>),
   ok.
 
+put_if_absent(_) ->
+  Path = <<"/project/priv/db_put_if_absent">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put_if_absent(Db, <<"key">>, <<"v1">>),
+  {error, {mismatch, <<"v1">>}} =
+    rocker:put_if_absent(Db, <<"key">>, <<"v2">>),
+  {ok, <<"v1">>} = rocker:get(Db, <<"key">>),
+  ok.
+
+compare_and_swap(_) ->
+  Path = <<"/project/priv/db_compare_and_swap">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:compare_and_swap(Db, <<"key">>, notfound, <<"v1">>),
+  {error, {mismatch, <<"v1">>}} =
+    rocker:compare_and_swap(Db, <<"key">>, notfound, <<"v2">>),
+  {error, {mismatch, <<"v1">>}} =
+    rocker:compare_and_swap(Db, <<"key">>, <<"v0">>, <<"v2">>),
+  ok = rocker:compare_and_swap(Db, <<"key">>, <<"v1">>, <<"v2">>),
+  {ok, <<"v2">>} = rocker:get(Db, <<"key">>),
+  {error, {mismatch, <<"v2">>}} =
+    rocker:delete_if_equals(Db, <<"key">>, <<"v1">>),
+  ok = rocker:delete_if_equals(Db, <<"key">>, <<"v2">>),
+  notfound = rocker:get(Db, <<"key">>),
+  {error, {mismatch, notfound}} =
+    rocker:delete_if_equals(Db, <<"key">>, <<"v2">>),
+  {'EXIT', {badarg, _}} =
+    (catch rocker:compare_and_swap(Db, <<"key">>, notfound, value)),
+  ok.
+
+compare_and_swap_term(_) ->
+  Path = <<"/project/priv/db_compare_and_swap_term">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => term_compressed
+  }),
+  Val = #{tags => lists:seq(1, 100)},
+  ok = rocker:put_if_absent(Db, <<"key">>, Val),
+  %% values are compared as terms, not by their encoding
+  ok = rocker:compare_and_swap(Db, <<"key">>, Val, {v, 2}),
+  {error, {mismatch, {v, 2}}} =
+    rocker:compare_and_swap(Db, <<"key">>, {v, 2.0}, {v, 3}),
+  ok = rocker:delete_if_equals(Db, <<"key">>, {v, 2}),
+  ok.
+
+compare_and_swap_cf(_) ->
+  Path = <<"/project/priv/db_compare_and_swap_cf">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  {ok, Cf} = rocker:cf_handle(Db, <<"testcf">>),
+  ok = rocker:put_if_absent_cf(Db, <<"testcf">>, <<"key">>, <<"v1">>),
+  {error, {mismatch, <<"v1">>}} =
+    rocker:put_if_absent_cf(Db, Cf, <<"key">>, <<"v2">>),
+  ok = rocker:put_if_absent(Db, <<"key">>, <<"v">>),
+  ok = rocker:compare_and_swap_cf(Db, Cf, <<"key">>, <<"v1">>, <<"v2">>),
+  {error, {mismatch, <<"v2">>}} =
+    rocker:delete_if_equals_cf(Db, <<"testcf">>, <<"key">>, <<"v1">>),
+  ok = rocker:delete_if_equals_cf(Db, <<"testcf">>, <<"key">>, <<"v2">>),
+  notfound = rocker:get_cf(Db, Cf, <<"key">>),
+  {ok, <<"v">>} = rocker:get(Db, <<"key">>),
+  {error, {unknown_cf, <<"nocf">>}} =
+    rocker:put_if_absent_cf(Db, <<"nocf">>, <<"key">>, <<"v">>),
+  ok.
+
+compare_and_swap_concurrent(_) ->
+  Path = <<"/project/priv/db_compare_and_swap_concurrent">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => term
+  }),
+  ok = rocker:put(Db, <<"counter">>, 0),
+  Self = self(),
+  Workers = 8,
+  Incrs = 100,
+  [spawn(fun() ->
+    [cas_incr(Db, <<"counter">>) || _ <- lists:seq(1, Incrs)],
+    Self ! done
+  end) || _ <- lists:seq(1, Workers)],
+  [receive done -> ok after 10000 -> error(timeout) end
+    || _ <- lists:seq(1, Workers)],
+  Total = Workers * Incrs,
+  {ok, Total} = rocker:get(Db, <<"counter">>),
+  ok.
+
+cas_incr(Db, Key) ->
+  {ok, N} = rocker:get(Db, Key),
+  case rocker:compare_and_swap(Db, Key, N, N + 1) of
+    ok -> ok;
+    {error, {mismatch, _}} -> cas_incr(Db, Key)
+  end.
+
+incr(_) ->
+  Path = <<"/project/priv/db_incr">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  notfound = rocker:counter_get(Db, <<"c">>),
+  {ok, 5} = rocker:incr(Db, <<"c">>, 5),
+  {ok, -2} = rocker:incr(Db, <<"c">>, -7),
+  {ok, -2} = rocker:counter_get(Db, <<"c">>),
+  {ok, <<-2:64/signed-big>>} = rocker:get(Db, <<"c">>),
+  ok = rocker:put(Db, <<"max">>, <<16#7fffffffffffffff:64/signed-big>>),
+  {error, {invalid_argument, _}} = rocker:incr(Db, <<"max">>, 1),
+  ok = rocker:put(Db, <<"v">>, <<"value">>),
+  {error, {invalid_argument, _}} = rocker:incr(Db, <<"v">>, 1),
+  {error, {invalid_argument, _}} = rocker:counter_get(Db, <<"v">>),
+  {'EXIT', {badarg, _}} = (catch rocker:incr(Db, <<"c">>, 1.0)),
+  ok.
+
+incr_cf(_) ->
+  Path = <<"/project/priv/db_incr_cf">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  {ok, Cf} = rocker:cf_handle(Db, <<"testcf">>),
+  {ok, 1} = rocker:incr_cf(Db, <<"testcf">>, <<"c">>, 1),
+  {ok, 3} = rocker:incr_cf(Db, Cf, <<"c">>, 2),
+  {ok, <<3:64/signed-big>>} = rocker:get_cf(Db, Cf, <<"c">>),
+  notfound = rocker:counter_get(Db, <<"c">>),
+  {error, {unknown_cf, <<"nocf">>}} =
+    rocker:incr_cf(Db, <<"nocf">>, <<"c">>, 1),
+  ok.
+
+incr_concurrent(_) ->
+  Path = <<"/project/priv/db_incr_concurrent">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  Self = self(),
+  Workers = 8,
+  Incrs = 500,
+  [spawn(fun() ->
+    [{ok, _} = rocker:incr(Db, <<"c">>, 1) || _ <- lists:seq(1, Incrs)],
+    [{ok, _} = rocker:tx(Db, [{incr, <<"c">>, 1}])
+      || _ <- lists:seq(1, Incrs)],
+    Self ! done
+  end) || _ <- lists:seq(1, Workers)],
+  [receive done -> ok after 10000 -> error(timeout) end
+    || _ <- lists:seq(1, Workers)],
+  Total = 2 * Workers * Incrs,
+  {ok, Total} = rocker:counter_get(Db, <<"c">>),
+  ok.
+
+incr_tx(_) ->
+  Path = <<"/project/priv/db_incr_tx">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  {ok, 10} = rocker:incr(Db, <<"c1">>, 10),
+  {ok, 3} = rocker:tx(Db, [
+    {put, <<"k">>, <<"v">>},
+    {incr, <<"c1">>, 1},
+    {incr, <<"c1">>, 2},
+    {incr, <<"testcf">>, <<"c2">>, -1}
+  ]),
+  {ok, 13} = rocker:counter_get(Db, <<"c1">>),
+  {ok, <<-1:64/signed-big>>} = rocker:get_cf(Db, <<"testcf">>, <<"c2">>),
+  {error, {bad_op, 2, {incr, <<"c1">>, one}}} =
+    rocker:tx(Db, [{incr, <<"c1">>, 1}, {incr, <<"c1">>, one}]),
+  %% a counter that can't be incremented fails the whole tx
+  {error, {invalid_argument, _}} =
+    rocker:tx(Db, [{incr, <<"c1">>, 1}, {incr, <<"k">>, 1}]),
+  {ok, 13} = rocker:counter_get(Db, <<"c1">>),
+  ok.
+
 write_batch(_) ->
   Path = <<"/project/priv/db_bath">>,
   {ok, Db} = rocker:open_default(Path),
@@ -188,6 +580,60 @@ write_batch(_) ->
   {ok, <<"v3">>} = rocker:get(Db, <<"k3">>),
   ok.
 
+write_batch_large(_) ->
+  Path = <<"/project/priv/db_batch_large">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  N = 200000,
+  Ops = [{put, integer_to_binary(I), integer_to_binary(I)}
+    || I <- lists:seq(1, N)],
+  {ok, N} = rocker:tx(Db, Ops),
+  notfound = rocker:get(Db, <<"0">>),
+  {ok, <<"1">>} = rocker:get(Db, <<"1">>),
+  {ok, <<"200000">>} = rocker:get(Db, <<"200000">>),
+  ok.
+
+write_batch_bad_op(_) ->
+  Path = <<"/project/priv/db_batch_bad_op">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  Put = {put, <<"k1">>, <<"v1">>},
+  lists:foreach(fun(Op) ->
+    {error, {bad_op, 2, Op}} = rocker:tx(Db, [Put, Op, Put])
+  end, [
+    {delete, <<"k0">>, <<"v0">>},
+    {put, <<"k2">>},
+    {put, <<"k2">>, <<"v2">>, <<"v3">>},
+    {put, k2, <<"v2">>},
+    {put, <<"k2">>, 2},
+    {put_cf, 1, <<"k2">>, <<"v2">>},
+    {delete_cf, <<"cf">>},
+    {merge, <<"k2">>, <<"v2">>},
+    {<<"put">>, <<"k2">>, <<"v2">>},
+    {},
+    put,
+    <<"k2">>
+  ]),
+  Ops = [Put || _ <- lists:seq(1, 100000)],
+  {error, {bad_op, 100001, {delete}}} = rocker:tx(Db, Ops ++ [{delete}]),
+  Ref = make_ref(),
+  ok = rocker:tx_async(Db, [bad], Ref),
+  {error, {bad_op, 1, bad}} = await(Ref),
+  notfound = rocker:get(Db, <<"k1">>),
+  {'EXIT', {badarg, _}} = (catch rocker:tx(Db, [Put | bad])),
+  ok.
+
+write_batch_large_error(_) ->
+  Path = <<"/project/priv/db_batch_large_error">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  Ops = [{put, integer_to_binary(I), integer_to_binary(I)}
+    || I <- lists:seq(1, 200000)],
+  {error, {unknown_cf, 200001, <<"nocf">>}} =
+    rocker:tx(Db, Ops ++ [{put_cf, <<"nocf">>, <<"k">>, <<"v">>}]),
+  notfound = rocker:get(Db, <<"1">>),
+  ok.
+
 %% =============================================================================
 %% group: iterator
 %% =============================================================================
@@ -294,6 +740,43 @@ prefix_iterator(_) ->
 
   ok.
 
+iterator_keeps_db(_) ->
+  Path = <<"/project/priv/db_iterator_keeps_db">>,
+  rocker:destroy(Path),
+  Iter = iterator_without_db(Path),
+  erlang:garbage_collect(),
+  {error, {io_error, _}} = rocker:open_default(Path),
+  {ok, <<"k1">>, <<"v1">>} = rocker:next(Iter),
+  {ok, <<"k2">>, <<"v2">>} = rocker:next(Iter),
+  ok = rocker:next(Iter),
+  ok.
+
+iterator_without_db(Path) ->
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  ok = rocker:put(Db, <<"k2">>, <<"v2">>),
+  {ok, Iter} = rocker:iterator(Db, {'start'}),
+  Iter.
+
+iterator_cf_dropped(_) ->
+  Path = <<"/project/priv/db_iterator_cf_dropped">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  ok = rocker:put_cf(Db, <<"testcf">>, <<"k1">>, <<"v1">>),
+  {ok, Iter} = rocker:iterator(Db, {'start'}),
+  {ok, CfIter} = rocker:iterator_cf(Db, <<"testcf">>, {'start'}),
+  {ok, CfPrefixIter} = rocker:prefix_iterator_cf(Db, <<"testcf">>, <<"k">>),
+  ok = rocker:drop_cf(Db, <<"testcf">>),
+  {error, cf_dropped} = rocker:next(CfIter),
+  {error, cf_dropped} = rocker:iterator_valid(CfIter),
+  {error, cf_dropped} = rocker:next(CfPrefixIter),
+  {ok, <<"k1">>, <<"v1">>} = rocker:next(Iter),
+  ok = rocker:close(Db),
+  {error, closed} = rocker:next(CfIter),
+  ok.
+
 %% =============================================================================
 %% group: cf
 %% =============================================================================
@@ -317,7 +800,7 @@ open_cf_default(_) ->
         end),
   receive
     ok ->
-      {err, _} = rocker:open_default(Path),
+      {error, {invalid_argument, _}} = rocker:open_default(Path),
       {ok, Db} = rocker:open_cf_default(
         Path, [<<"testcf1">>, <<"testcf2">>, <<"testcf3">>]
       ),
@@ -342,7 +825,16 @@ drop_cf(_) ->
   {ok, [<<"default">>, <<"testcf">>]} = rocker:list_cf(Path),
   ok = rocker:drop_cf(Db, <<"testcf">>),
   {ok, [<<"default">>]} = rocker:list_cf(Path),
-  {err, _} = rocker:drop_cf(Db, <<"testcf">>),
+  {error, {unknown_cf, <<"testcf">>}} = rocker:drop_cf(Db, <<"testcf">>),
+  %% by handle, which stays dropped when the name is reused
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  {ok, Cf} = rocker:cf_handle(Db, <<"testcf">>),
+  ok = rocker:drop_cf(Db, Cf),
+  {ok, [<<"default">>]} = rocker:list_cf(Path),
+  {error, cf_dropped} = rocker:get_cf(Db, Cf, <<"k1">>),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  {error, cf_dropped} = rocker:drop_cf(Db, Cf),
+  {ok, [<<"default">>, <<"testcf">>]} = rocker:list_cf(Path),
   ok.
 
 put_cf_get_cf(_) ->
@@ -426,10 +918,8 @@ create_iterator_cf_not_found_cf(_) ->
   rocker:destroy(Path),
   {ok, Db} = rocker:open_default(Path),
   Cf = <<"test_cf">>,
-  case catch rocker:iterator_cf(Db, Cf, {'start'}) of
-    {'EXIT', {nif_panic,_}} -> good;
-    _Else -> throw({err, should_be_panic_here, _Else})
-  end,
+  {error, {unknown_cf, Cf}} = rocker:iterator_cf(Db, Cf, {'start'}),
+  {error, {unknown_cf, Cf}} = rocker:prefix_iterator_cf(Db, Cf, <<"k">>),
   ok.
 
 next_start_cf(_) ->
@@ -541,17 +1031,17 @@ write_batch_cf(_) ->
   {ok, 12} = rocker:tx(Db, [
     {put, <<"k1">>, <<"v1">>},
     {put, <<"k2">>, <<"v2">>},
-    {delete, <<"k0">>, <<"v0">>},
+    {delete, <<"k0">>},
     {put, <<"k3">>, <<"v3">>},
 
     {put_cf, Cf1, <<"k1">>, <<"v1">>},
     {put_cf, Cf1, <<"k2">>, <<"v2">>},
-    {delete_cf, Cf1, <<"k0">>, <<"v0">>},
+    {delete_cf, Cf1, <<"k0">>},
     {put_cf, Cf1, <<"k3">>, <<"v3">>},
 
     {put_cf, Cf2, <<"k1">>, <<"v1">>},
     {put_cf, Cf2, <<"k2">>, <<"v2">>},
-    {delete_cf, Cf2, <<"k0">>, <<"v0">>},
+    {delete_cf, Cf2, <<"k0">>},
     {put_cf, Cf2, <<"k3">>, <<"v3">>}
   ]),
 
@@ -572,22 +1062,1672 @@ write_batch_cf(_) ->
 
   ok.
 
+unknown_cf_ops(_) ->
+  Path = <<"/project/priv/db_unknown_cf_ops">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  Cf = <<"test_cf">>,
+  {error, {unknown_cf, Cf}} = rocker:put_cf(Db, Cf, <<"k">>, <<"v">>),
+  {error, {unknown_cf, Cf}} = rocker:get_cf(Db, Cf, <<"k">>),
+  {error, {unknown_cf, Cf}} = rocker:delete_cf(Db, Cf, <<"k">>),
+  ok = rocker:create_cf_default(Db, Cf),
+  ok = rocker:put_cf(Db, Cf, <<"k">>, <<"v">>),
+  ok = rocker:drop_cf(Db, Cf),
+  {error, {unknown_cf, Cf}} = rocker:get_cf(Db, Cf, <<"k">>),
+  ok.
+
+unknown_cf_tx(_) ->
+  Path = <<"/project/priv/db_unknown_cf_tx">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  Cf = <<"test_cf">>,
+  {error, {unknown_cf, 2, Cf}} = rocker:tx(Db, [
+    {put, <<"k1">>, <<"v1">>},
+    {put_cf, Cf, <<"k2">>, <<"v2">>}
+  ]),
+  {error, {unknown_cf, 1, Cf}} = rocker:tx(Db, [
+    {delete_cf, Cf, <<"k2">>}
+  ]),
+  notfound = rocker:get(Db, <<"k1">>),
+  Ref = make_ref(),
+  ok = rocker:tx_async(Db, [
+    {put, <<"k1">>, <<"v1">>},
+    {put_cf, Cf, <<"k2">>, <<"v2">>}
+  ], Ref),
+  {error, {unknown_cf, 2, Cf}} = await(Ref),
+  notfound = rocker:get(Db, <<"k1">>),
+  %% one reply only
+  receive
+    {rocker_reply, Ref, _} -> error(unexpected_reply)
+  after 100 ->
+    ok
+  end.
+
+cf_handle(_) ->
+  Path = <<"/project/priv/db_cf_handle">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => term
+  }),
+  ok = rocker:create_cf(Db, <<"testcf">>, #{value_codec => zstd}),
+  {error, {unknown_cf, <<"nocf">>}} = rocker:cf_handle(Db, <<"nocf">>),
+  {ok, Cf} = rocker:cf_handle(Db, <<"testcf">>),
+  true = is_reference(Cf),
+  ok = rocker:put_cf(Db, Cf, <<"k1">>, {v, 1}),
+  {ok, {v, 1}} = rocker:get_cf(Db, <<"testcf">>, <<"k1">>),
+  {ok, {v, 1}} = rocker:get_cf(Db, Cf, <<"k1">>),
+  {ok, 2} = rocker:tx(Db, [
+    {put_cf, Cf, <<"k2">>, {v, 2}},
+    {delete_cf, Cf, <<"k1">>}
+  ]),
+  notfound = rocker:get_cf(Db, Cf, <<"k1">>),
+  {ok, Iter} = rocker:iterator_cf(Db, Cf, {'start'}),
+  {ok, <<"k2">>, {v, 2}} = rocker:next(Iter),
+  ok = rocker:next(Iter),
+  {ok, PrefixIter} = rocker:prefix_iterator_cf(Db, Cf, <<"k">>),
+  {ok, <<"k2">>, {v, 2}} = rocker:next(PrefixIter),
+  ok = rocker:delete_cf(Db, Cf, <<"k2">>),
+  notfound = rocker:get_cf(Db, Cf, <<"k2">>),
+  ok.
+
+cf_handle_dropped(_) ->
+  Path = <<"/project/priv/db_cf_handle_dropped">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  {ok, Cf} = rocker:cf_handle(Db, <<"testcf">>),
+  ok = rocker:drop_cf(Db, <<"testcf">>),
+  {error, cf_dropped} = rocker:put_cf(Db, Cf, <<"k1">>, <<"v1">>),
+  {error, cf_dropped} = rocker:get_cf(Db, Cf, <<"k1">>),
+  {error, cf_dropped} = rocker:iterator_cf(Db, Cf, {'start'}),
+  {error, {cf_dropped, 1}} =
+    rocker:tx(Db, [{put_cf, Cf, <<"k1">>, <<"v1">>}]),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  {error, cf_dropped} = rocker:get_cf(Db, Cf, <<"k1">>),
+  ok = rocker:close(Db),
+  {error, closed} = rocker:get_cf(Db, Cf, <<"k1">>),
+  ok.
+
+cf_handle_other_db(_) ->
+  Path1 = <<"/project/priv/db_cf_handle_other_db1">>,
+  Path2 = <<"/project/priv/db_cf_handle_other_db2">>,
+  rocker:destroy(Path1),
+  rocker:destroy(Path2),
+  {ok, Db1} = rocker:open_default(Path1),
+  {ok, Db2} = rocker:open_default(Path2),
+  ok = rocker:create_cf_default(Db1, <<"testcf">>),
+  ok = rocker:create_cf_default(Db2, <<"testcf">>),
+  {ok, Cf1} = rocker:cf_handle(Db1, <<"testcf">>),
+  {'EXIT', {badarg, _}} =
+    (catch rocker:put_cf(Db2, Cf1, <<"k1">>, <<"v1">>)),
+  {'EXIT', {badarg, _}} = (catch rocker:drop_cf(Db2, Cf1)),
+  ok.
+
+open_cf_default_missing_cf(_) ->
+  Path = <<"/project/priv/db_open_cf_default_missing_cf">>,
+  rocker:destroy(Path),
+  {error, _} = rocker:open_cf_default(Path, [<<"test_cf">>]),
+  ok.
+
 %% =============================================================================
-%% group: perf
+%% group: key_codec
 %% =============================================================================
-perf_default(_) ->
-  Path = <<"/project/priv/perf_default">>,
+key_encode_decode(_) ->
+  Terms = [0, 1, -1, 1 bsl 60, -(1 bsl 60), 1.5, -2.25, 0.0,
+    atom, '', <<>>, <<0, 1, 0>>, <<"bin">>,
+    {}, {tenant, 1588000000, <<"id">>}, [], [1, [2, {3}], <<"x">>],
+    {[a, b], {<<0>>, -7.0}}],
+  lists:foreach(fun(T) ->
+    K = rocker_key:encode(T),
+    true = is_binary(K),
+    T = rocker_key:decode(K)
+                end, Terms),
+  ok.
+
+key_encode_order(_) ->
+  Terms = [-(1 bsl 62), -1000, -1.5, -1, 0, 0.5, 1, 2, 2.5, 1 bsl 53,
+    (1 bsl 53) + 1, a, aa, b, {}, {z}, {a, a}, {a, b}, {b, a}, [], [1],
+    [1, 2], [2], [a], <<>>, <<0>>, <<0, 0>>, <<1>>, <<"a">>, <<"ab">>, <<"b">>],
+  Sorted = lists:sort(Terms),
+  Encoded = lists:sort([rocker_key:encode(T) || T <- Terms]),
+  Sorted = [rocker_key:decode(K) || K <- Encoded],
+  ok.
+
+key_encode_badarg(_) ->
+  {'EXIT', {badarg, _}} = (catch rocker_key:encode(self())),
+  {'EXIT', {badarg, _}} = (catch rocker_key:encode(#{a => 1})),
+  %% would otherwise encode like [a] and [{k, a}]
+  {'EXIT', {badarg, _}} = (catch rocker_key:encode([a | b])),
+  {'EXIT', {badarg, _}} = (catch rocker_key:encode({k, [a | b]})),
+  {'EXIT', {badarg, _}} = (catch rocker:key_encode([a, b | c])),
+  {'EXIT', {badarg, _}} = (catch rocker_key:decode(<<255>>)),
+  %% the arity of a tuple doesn't preallocate past the input
+  {'EXIT', {badarg, _}} =
+    (catch rocker_key:decode(<<16#50, 255, 255, 255, 255>>)),
+  %% nesting is limited instead of overflowing the stack
+  {'EXIT', {badarg, _}} =
+    (catch rocker_key:decode(binary:copy(<<16#50, 0, 0, 0, 1>>, 1000000))),
+  Deep = lists:foldl(fun(_, T) -> {T} end, a, lists:seq(1, 1000)),
+  {'EXIT', {badarg, _}} = (catch rocker_key:encode(Deep)),
+  Nested = lists:foldl(fun(_, T) -> [T] end, a, lists:seq(1, 100)),
+  Nested = rocker_key:decode(rocker_key:encode(Nested)),
+  ok.
+
+key_codec_put_get(_) ->
+  Path = <<"/project/priv/db_key_codec_put">>,
   rocker:destroy(Path),
-  {ok, Db} = rocker:open_default(Path),
-  W = perftest:comprehensive(1000, fun() ->
-    I = list_to_binary(uuid:uuid_to_string(uuid:get_v4())),
-    ok = rocker:put(Db, I, I)
-                                   end),
-  true = lists:all(fun(E) -> E >= 5000 end, W),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    key_codec => sortable_term
+  }),
+  ok = rocker:put(Db, {tenant1, 100, <<"id1">>}, <<"v1">>),
+  {ok, <<"v1">>} = rocker:get(Db, {tenant1, 100, <<"id1">>}),
+  notfound = rocker:get(Db, {tenant1, 100, <<"id2">>}),
+  ok = rocker:delete(Db, {tenant1, 100, <<"id1">>}),
+  notfound = rocker:get(Db, {tenant1, 100, <<"id1">>}),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  ok = rocker:put_cf(Db, <<"testcf">>, [k, 1], <<"v">>),
+  {ok, <<"v">>} = rocker:get_cf(Db, <<"testcf">>, [k, 1]),
+  ok = rocker:delete_cf(Db, <<"testcf">>, [k, 1]),
+  notfound = rocker:get_cf(Db, <<"testcf">>, [k, 1]),
+  ok.
 
-  ok = rocker:put(Db, <<"k0">>, <<"v0">>),
-  R = perftest:comprehensive(1000, fun() ->
-    {ok, <<"v0">>} = rocker:get(Db, <<"k0">>)
-                                   end),
-  true = lists:all(fun(E) -> E >= 5000 end, R),
+key_codec_iterator(_) ->
+  Path = <<"/project/priv/db_key_codec_iter">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    key_codec => sortable_term
+  }),
+  ok = rocker:put(Db, {t1, 9, <<"a">>}, <<"v9">>),
+  ok = rocker:put(Db, {t1, 10, <<"a">>}, <<"v10">>),
+  ok = rocker:put(Db, {t1, 100, <<"a">>}, <<"v100">>),
+  ok = rocker:put(Db, {t2, 1, <<"a">>}, <<"v1">>),
+  {ok, Iter} = rocker:iterator(Db, {'from', {t1, 10, <<>>}, forward}),
+  {ok, {t1, 10, <<"a">>}, <<"v10">>} = rocker:next(Iter),
+  {ok, {t1, 100, <<"a">>}, <<"v100">>} = rocker:next(Iter),
+  {ok, {t2, 1, <<"a">>}, <<"v1">>} = rocker:next(Iter),
+  ok = rocker:next(Iter),
+  {ok, RevIter} = rocker:iterator(Db, {'from', {t1, 50, <<>>}, reverse}),
+  {ok, {t1, 10, <<"a">>}, <<"v10">>} = rocker:next(RevIter),
+  {ok, {t1, 9, <<"a">>}, <<"v9">>} = rocker:next(RevIter),
+  ok = rocker:next(RevIter),
+  ok.
+
+key_codec_tx(_) ->
+  Path = <<"/project/priv/db_key_codec_tx">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    key_codec => sortable_term
+  }),
+  ok = rocker:put(Db, {k, 0}, <<"v0">>),
+  {ok, 3} = rocker:tx(Db, [
+    {put, {k, 1}, <<"v1">>},
+    {put, {k, 2}, <<"v2">>},
+    {delete, {k, 0}}
+  ]),
+  notfound = rocker:get(Db, {k, 0}),
+  {ok, <<"v1">>} = rocker:get(Db, {k, 1}),
+  {ok, <<"v2">>} = rocker:get(Db, {k, 2}),
+  ok.
+
+key_codec_reopen_cf(_) ->
+  Path = <<"/project/priv/db_key_codec_reopen_cf">>,
+  rocker:destroy(Path),
+  Opts = #{create_if_missing => true, key_codec => sortable_term},
+  {ok, Db} = rocker:open(Path, Opts),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  ok = rocker:put_cf(Db, <<"testcf">>, {k, 2}, <<"v2">>),
+  ok = rocker:put_cf(Db, <<"testcf">>, {k, 1}, <<"v1">>),
+  ok = rocker:close(Db),
+  {ok, Db2} = rocker:open_cf(Path, Opts, #{<<"testcf">> => #{}}),
+  {ok, <<"v1">>} = rocker:get_cf(Db2, <<"testcf">>, {k, 1}),
+  {ok, Iter} = rocker:iterator_cf(Db2, <<"testcf">>, {'start'}),
+  {ok, {k, 1}, <<"v1">>} = rocker:next(Iter),
+  {ok, {k, 2}, <<"v2">>} = rocker:next(Iter),
+  ok.
+
+%% =============================================================================
+%% group: value_codec
+%% =============================================================================
+value_codec_raw(_) ->
+  Path = <<"/project/priv/db_value_codec_raw">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => raw
+  }),
+  ok = rocker:put(Db, <<"key">>, <<"value">>),
+  {ok, <<"value">>} = rocker:get(Db, <<"key">>),
+  {'EXIT', {badarg, _}} = (catch rocker:put(Db, <<"key">>, {not_binary})),
+  ok.
+
+value_codec_term(_) ->
+  Path = <<"/project/priv/db_value_codec_term">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => term
+  }),
+  Val = #{name => <<"rocker">>, tags => [a, b, c], n => 42},
+  ok = rocker:put(Db, <<"key">>, Val),
+  {ok, Val} = rocker:get(Db, <<"key">>),
+  ok = rocker:put(Db, <<"bin">>, <<"binary is a term too">>),
+  {ok, <<"binary is a term too">>} = rocker:get(Db, <<"bin">>),
+  ok.
+
+value_codec_term_compressed(_) ->
+  Path = <<"/project/priv/db_value_codec_term_compressed">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => term_compressed
+  }),
+  Small = {small, 1},
+  Large = lists:duplicate(10000, {large, <<"payload">>}),
+  ok = rocker:put(Db, <<"small">>, Small),
+  ok = rocker:put(Db, <<"large">>, Large),
+  {ok, Small} = rocker:get(Db, <<"small">>),
+  {ok, Large} = rocker:get(Db, <<"large">>),
+  ok.
+
+value_codec_zstd(_) ->
+  Path = <<"/project/priv/db_value_codec_zstd">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => zstd
+  }),
+  Val = lists:duplicate(10000, {zstd, <<"payload">>}),
+  ok = rocker:put(Db, <<"key">>, Val),
+  {ok, Val} = rocker:get(Db, <<"key">>),
+  ok.
+
+value_codec_cf(_) ->
+  Path = <<"/project/priv/db_value_codec_cf">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => term
+  }),
+  ok = rocker:create_cf(Db, <<"rawcf">>, #{value_codec => raw}),
+  ok = rocker:create_cf_default(Db, <<"termcf">>),
+  ok = rocker:put_cf(Db, <<"rawcf">>, <<"key">>, <<"value">>),
+  {ok, <<"value">>} = rocker:get_cf(Db, <<"rawcf">>, <<"key">>),
+  ok = rocker:put_cf(Db, <<"termcf">>, <<"key">>, {term, value}),
+  {ok, {term, value}} = rocker:get_cf(Db, <<"termcf">>, <<"key">>),
+  ok = rocker:put(Db, <<"key">>, [term]),
+  ok = rocker:close(Db),
+  %% the codecs are options, they are given again when reopening
+  {ok, Db2} = rocker:open_cf(Path, #{value_codec => term}, #{
+    <<"rawcf">> => #{value_codec => raw},
+    <<"termcf">> => #{}
+  }),
+  {ok, <<"value">>} = rocker:get_cf(Db2, <<"rawcf">>, <<"key">>),
+  {ok, {term, value}} = rocker:get_cf(Db2, <<"termcf">>, <<"key">>),
+  {ok, [term]} = rocker:get(Db2, <<"key">>),
+  {ok, Iter} = rocker:iterator_cf(Db2, <<"termcf">>, {'start'}),
+  {ok, <<"key">>, {term, value}} = rocker:next(Iter),
+  ok = rocker:close(Db2),
+  {error, {unknown_option, key_codec}} =
+    rocker:open_cf(Path, #{}, #{<<"rawcf">> => #{key_codec => raw}}),
+  {error, {invalid_argument, _}} =
+    rocker:open_cf(Path, #{}, #{<<"rawcf">> => #{}}),
   ok.
+
+value_codec_iterator(_) ->
+  Path = <<"/project/priv/db_value_codec_iter">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => term_compressed
+  }),
+  ok = rocker:put(Db, <<"k0">>, {v, 0}),
+  ok = rocker:put(Db, <<"k1">>, [v, 1]),
+  {ok, Iter} = rocker:iterator(Db, {'start'}),
+  {ok, <<"k0">>, {v, 0}} = rocker:next(Iter),
+  {ok, <<"k1">>, [v, 1]} = rocker:next(Iter),
+  ok = rocker:next(Iter),
+  ok.
+
+value_codec_tx(_) ->
+  Path = <<"/project/priv/db_value_codec_tx">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => term
+  }),
+  ok = rocker:create_cf(Db, <<"zcf">>, #{value_codec => zstd}),
+  {ok, 2} = rocker:tx(Db, [
+    {put, <<"k1">>, {v, 1}},
+    {put_cf, <<"zcf">>, <<"k2">>, {v, 2}}
+  ]),
+  {ok, {v, 1}} = rocker:get(Db, <<"k1">>),
+  {ok, {v, 2}} = rocker:get_cf(Db, <<"zcf">>, <<"k2">>),
+  ok.
+
+multi_get(_) ->
+  Path = <<"/project/priv/db_multi_get">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => term
+  }),
+  ok = rocker:put(Db, <<"k1">>, {v, 1}),
+  ok = rocker:put(Db, <<"k2">>, {v, 2}),
+  {ok, [{ok, {v, 1}}, notfound, {ok, {v, 2}}]} =
+    rocker:multi_get(Db, [<<"k1">>, <<"k0">>, <<"k2">>]),
+  {ok, []} = rocker:multi_get(Db, []),
+  ok.
+
+multi_get_large(_) ->
+  Path = <<"/project/priv/db_multi_get_large">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  Keys = [integer_to_binary(I) || I <- lists:seq(1, 100000)],
+  {ok, 50000} = rocker:tx(Db, [{put, K, K} || K <- Keys,
+    binary_to_integer(K) rem 2 =:= 0]),
+  {ok, Results} = rocker:multi_get(Db, Keys),
+  Expected = [case binary_to_integer(K) rem 2 of
+                0 -> {ok, K};
+                1 -> notfound
+              end || K <- Keys],
+  Expected = Results,
+  ok.
+
+%% =============================================================================
+%% group: wal
+%% =============================================================================
+latest_sequence_number(_) ->
+  Path = <<"/project/priv/db_wal_seqno">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, 0} = rocker:latest_sequence_number(Db),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  {ok, 1} = rocker:latest_sequence_number(Db),
+  {ok, 2} = rocker:tx(Db, [
+    {put, <<"k2">>, <<"v2">>},
+    {delete, <<"k1">>}
+  ]),
+  {ok, 3} = rocker:latest_sequence_number(Db),
+  ok.
+
+get_updates_since(_) ->
+  Path = <<"/project/priv/db_wal_updates">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    wal_ttl_seconds => 3600,
+    wal_size_limit_mb => 64
+  }),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  ok = rocker:put(Db, <<"k2">>, <<"v2">>),
+  {ok, 2} = rocker:tx(Db, [
+    {put, <<"k3">>, <<"v3">>},
+    {delete, <<"k1">>}
+  ]),
+  {ok, Wal} = rocker:get_updates_since(Db, 1),
+  true = is_reference(Wal),
+  {1, [{put, 0, <<"k1">>, <<"v1">>}]} = rocker:wal_next(Wal),
+  {2, [{put, 0, <<"k2">>, <<"v2">>}]} = rocker:wal_next(Wal),
+  {3, [{put, 0, <<"k3">>, <<"v3">>}, {delete, 0, <<"k1">>}]} =
+    rocker:wal_next(Wal),
+  ok = rocker:wal_next(Wal),
+
+  {ok, Wal2} = rocker:get_updates_since(Db, 3),
+  {3, [_, _]} = rocker:wal_next(Wal2),
+  ok = rocker:wal_next(Wal2),
+  ok.
+
+get_updates_since_cf_ops(_) ->
+  Path = <<"/project/priv/db_wal_updates_cf">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  ok = rocker:put_cf(Db, <<"testcf">>, <<"k1">>, <<"v1">>),
+  ok = rocker:delete_cf(Db, <<"testcf">>, <<"k1">>),
+  {ok, Wal} = rocker:get_updates_since(Db, 1),
+  {1, [{put, CfId, <<"k1">>, <<"v1">>}]} = rocker:wal_next(Wal),
+  true = CfId > 0,
+  {2, [{delete, CfId, <<"k1">>}]} = rocker:wal_next(Wal),
+  ok = rocker:wal_next(Wal),
+  ok.
+
+write_batch_data(_) ->
+  Ops = [
+    {put, 0, <<"k1">>, <<"v1">>},
+    {delete, 1, <<"k2">>},
+    {merge, 0, <<"k3">>, <<"v3">>},
+    {delete_range, 2, <<"a">>, <<"z">>}
+  ],
+  {ok, Data} = rocker:write_batch_data(Ops),
+  true = is_binary(Data),
+  {'EXIT', {badarg, _}} = (catch rocker:write_batch_data([{put, 0, <<"k">>}])),
+  {'EXIT', {badarg, _}} =
+    (catch rocker:write_batch_data([{unknown, 0, <<"k">>}])),
+  ok.
+
+apply_batch_data(_) ->
+  Path = <<"/project/priv/db_apply_batch_data">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  notfound = rocker:applied_sequence_number(Db),
+  {ok, Data1} = rocker:write_batch_data([
+    {put, 0, <<"k1">>, <<"v1">>},
+    {put, 0, <<"k2">>, <<"v2">>}
+  ]),
+  ok = rocker:apply_batch_data(Db, 1, Data1),
+  {ok, 2} = rocker:applied_sequence_number(Db),
+  {ok, <<"v1">>} = rocker:get(Db, <<"k1">>),
+  {ok, <<"v2">>} = rocker:get(Db, <<"k2">>),
+  {ok, Data2} = rocker:write_batch_data([{delete, 0, <<"k1">>}]),
+  ok = rocker:apply_batch_data(Db, 3, Data2),
+  notfound = rocker:get(Db, <<"k1">>),
+  skipped = rocker:apply_batch_data(Db, 1, Data1),
+  skipped = rocker:apply_batch_data(Db, 3, Data2),
+  notfound = rocker:get(Db, <<"k1">>),
+  {ok, 3} = rocker:applied_sequence_number(Db),
+  %% the last seqno of the batch overflows
+  {'EXIT', {badarg, _}} =
+    (catch rocker:apply_batch_data(Db, 16#FFFFFFFFFFFFFFFF, Data1)),
+  %% the marker's cf is the first one created here, and hidden
+  {ok, Internal} = rocker:write_batch_data([{put, 1, <<"k">>, <<"v">>}]),
+  {error, {invalid_argument, _}} = rocker:apply_batch_data(Db, 4, Internal),
+  {ok, [<<"default">>]} = rocker:list_cf(Path),
+  {error, {invalid_argument, _}} = rocker:create_cf_default(Db, <<"$rocker">>),
+  {error, {unknown_cf, <<"$rocker">>}} = rocker:cf_handle(Db, <<"$rocker">>),
+  {ok, 3} = rocker:applied_sequence_number(Db),
+  ok.
+
+apply_batch_data_order(_) ->
+  Path = <<"/project/priv/db_apply_batch_data_order">>,
+  rocker:destroy(Path),
+  Self = self(),
+  spawn(fun() ->
+    {ok, Db} = rocker:open_default(Path),
+    %% local writes don't move the marker
+    ok = rocker:put(Db, <<"local">>, <<"v">>),
+    notfound = rocker:applied_sequence_number(Db),
+    {ok, Data} = rocker:write_batch_data([{put, 0, <<"k1">>, <<"v1">>}]),
+    %% the leader's seqnos may have gaps
+    ok = rocker:apply_batch_data(Db, 10, Data),
+    {ok, 10} = rocker:applied_sequence_number(Db),
+    skipped = rocker:apply_batch_data(Db, 5, Data),
+    ok = rocker:put(Db, <<"local">>, <<"v2">>),
+    {ok, 10} = rocker:applied_sequence_number(Db),
+    Self ! ok
+        end),
+  receive
+    ok ->
+      {ok, Db} = rocker:open_default(Path),
+      {ok, 10} = rocker:applied_sequence_number(Db),
+      {ok, Data} = rocker:write_batch_data([
+        {put, 0, <<"k2">>, <<"v2">>},
+        {put, 0, <<"k3">>, <<"v3">>}
+      ]),
+      skipped = rocker:apply_batch_data(Db, 9, Data),
+      ok = rocker:apply_batch_data(Db, 20, Data),
+      {ok, 21} = rocker:applied_sequence_number(Db),
+      {ok, <<"v2">>} = rocker:get(Db, <<"k2">>),
+      {ok, <<"v2">>} = rocker:get(Db, <<"local">>)
+  end,
+  ok.
+
+replicate_follower(_) ->
+  LeaderPath = <<"/project/priv/db_replicate_leader">>,
+  FollowerPath = <<"/project/priv/db_replicate_follower">>,
+  rocker:destroy(LeaderPath),
+  rocker:destroy(FollowerPath),
+  {ok, Leader} = rocker:open(LeaderPath, #{
+    create_if_missing => true,
+    wal_ttl_seconds => 3600
+  }),
+  {ok, Follower} = rocker:open(FollowerPath, #{
+    create_if_missing => true,
+    wal_ttl_seconds => 3600
+  }),
+  ok = rocker:create_cf_default(Leader, <<"testcf">>),
+  ok = rocker:create_cf_default(Follower, <<"testcf">>),
+  lists:foreach(fun(I) ->
+    K = integer_to_binary(I),
+    ok = rocker:put(Leader, K, K)
+                end, lists:seq(1, 100)),
+  %% not in the log, leaves a gap in the leader's seqnos
+  {ok, Unlogged} = rocker:batch_new(),
+  ok = rocker:batch_put(Unlogged, <<"unlogged">>, <<"v">>),
+  ok = rocker:write_batch(Leader, Unlogged, #{disable_wal => true}),
+  ok = rocker:put(Follower, <<"local">>, <<"v">>),
+  {ok, 3} = rocker:tx(Leader, [
+    {delete, <<"1">>},
+    {put_cf, <<"testcf">>, <<"cfk">>, <<"cfv">>},
+    {put, <<"last">>, <<"v">>}
+  ]),
+  {ok, Wal} = rocker:get_updates_since(Leader, 1),
+  ok = ship(Wal, Follower),
+  {ok, Seq} = rocker:latest_sequence_number(Leader),
+  {ok, Seq} = rocker:applied_sequence_number(Follower),
+  {ok, LeaderIter} = rocker:iterator(Leader, {'start'}),
+  {ok, FollowerIter} = rocker:iterator(Follower, {'start'}),
+  Data = lists:keydelete(<<"unlogged">>, 1, drain(LeaderIter)),
+  100 = length(Data),
+  Data = lists:keydelete(<<"local">>, 1, drain(FollowerIter)),
+  {ok, LeaderCfIter} = rocker:iterator_cf(Leader, <<"testcf">>, {'start'}),
+  {ok, FollowerCfIter} = rocker:iterator_cf(Follower, <<"testcf">>, {'start'}),
+  [{<<"cfk">>, <<"cfv">>}] = CfData = drain(LeaderCfIter),
+  CfData = drain(FollowerCfIter),
+  %% the follower's log holds the same ops besides its own write, the
+  %% seqnos differ
+  {ok, LeaderWal} = rocker:get_updates_since(Leader, 1),
+  {ok, FollowerWal} = rocker:get_updates_since(Follower, 1),
+  Ops = [BatchOps || {_, BatchOps} <- drain_wal(LeaderWal)],
+  [[{put, 0, <<"local">>, <<"v">>}] | Ops] =
+    [BatchOps || {_, BatchOps} <- drain_wal(FollowerWal)],
+  ok.
+
+ship(Wal, Follower) ->
+  case rocker:wal_next(Wal) of
+    ok ->
+      ok;
+    {SeqNo, Ops} ->
+      {ok, Data} = rocker:write_batch_data(Ops),
+      ok = rocker:apply_batch_data(Follower, SeqNo, Data),
+      ship(Wal, Follower)
+  end.
+
+drain(Iter) ->
+  case rocker:next(Iter) of
+    ok -> [];
+    {ok, K, V} -> [{K, V} | drain(Iter)]
+  end.
+
+drain_wal(Wal) ->
+  case rocker:wal_next(Wal) of
+    ok -> [];
+    Batch -> [Batch | drain_wal(Wal)]
+  end.
+
+%% =============================================================================
+%% group: async
+%% =============================================================================
+get_async(_) ->
+  Path = <<"/project/priv/db_get_async">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  Ref = make_ref(),
+  ok = rocker:get_async(Db, <<"k1">>, Ref),
+  {ok, <<"v1">>} = await(Ref),
+  Ref2 = make_ref(),
+  ok = rocker:get_async(Db, <<"k0">>, Ref2),
+  notfound = await(Ref2),
+  ok.
+
+put_async(_) ->
+  Path = <<"/project/priv/db_put_async">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => term
+  }),
+  Ref = make_ref(),
+  ok = rocker:put_async(Db, <<"k1">>, {v, 1}, Ref),
+  ok = await(Ref),
+  {ok, {v, 1}} = rocker:get(Db, <<"k1">>),
+  ok.
+
+tx_async(_) ->
+  Path = <<"/project/priv/db_tx_async">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  ok = rocker:put(Db, <<"k0">>, <<"v0">>),
+  Ref = make_ref(),
+  ok = rocker:tx_async(Db, [
+    {put, <<"k1">>, <<"v1">>},
+    {put_cf, <<"testcf">>, <<"k2">>, <<"v2">>},
+    {delete, <<"k0">>}
+  ], Ref),
+  {ok, 3} = await(Ref),
+  notfound = rocker:get(Db, <<"k0">>),
+  {ok, <<"v1">>} = rocker:get(Db, <<"k1">>),
+  {ok, <<"v2">>} = rocker:get_cf(Db, <<"testcf">>, <<"k2">>),
+  Ref2 = make_ref(),
+  ok = rocker:tx_async(Db, [], Ref2),
+  {ok, 0} = await(Ref2),
+  %% ops are checked on the pool, a bad one is the reply
+  Ref3 = make_ref(),
+  ok = rocker:tx_async(Db, [{put, <<"k3">>, <<"v3">>}, {put, k}], Ref3),
+  {error, {bad_op, 2, {put, k}}} = await(Ref3),
+  Ref4 = make_ref(),
+  ok = rocker:tx_async(Db, [{put_cf, <<"nocf">>, <<"k">>, <<"v">>}], Ref4),
+  {error, {unknown_cf, 1, <<"nocf">>}} = await(Ref4),
+  notfound = rocker:get(Db, <<"k3">>),
+  Ref5 = make_ref(),
+  ok = rocker:tx_async(Db, [{put, <<"k3">>, <<"v3">>} | improper], Ref5),
+  {error, badarg} = await(Ref5),
+  {'EXIT', {badarg, _}} = (catch rocker:tx_async(Db, ops, make_ref())),
+  Ops = [{put, integer_to_binary(I), <<"v">>} || I <- lists:seq(1, 200000)],
+  Ref6 = make_ref(),
+  ok = rocker:tx_async(Db, Ops, Ref6),
+  {ok, 200000} = await(Ref6),
+  ok.
+
+tx_async_no_partial(_) ->
+  Path = <<"/project/priv/db_tx_async_no_partial">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  %% a bad op at the end of a list built over several slices
+  Ops = [{put, integer_to_binary(I), <<"v">>} || I <- lists:seq(1, 100000)],
+  Ref = make_ref(),
+  ok = rocker:tx_async(Db, Ops ++ [{put, <<"k">>}], Ref),
+  {error, {bad_op, 100001, {put, <<"k">>}}} = await(Ref),
+  Ref2 = make_ref(),
+  ok = rocker:tx_async(Db, Ops ++ [{put_cf, <<"nocf">>, <<"k">>, <<"v">>}],
+    Ref2),
+  {error, {unknown_cf, 100001, <<"nocf">>}} = await(Ref2),
+  notfound = rocker:get(Db, <<"1">>),
+  notfound = rocker:get(Db, <<"100000">>),
+  {ok, Iter} = rocker:iterator(Db, {'start'}),
+  [] = drain(Iter),
+  {ok, 0} = rocker:latest_sequence_number(Db),
+  ok.
+
+multi_get_async(_) ->
+  Path = <<"/project/priv/db_multi_get_async">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  ok = rocker:put(Db, <<"k2">>, <<"v2">>),
+  Ref = make_ref(),
+  ok = rocker:multi_get_async(Db, [<<"k1">>, <<"k0">>, <<"k2">>], Ref),
+  {ok, [{ok, <<"v1">>}, notfound, {ok, <<"v2">>}]} = await(Ref),
+  ok.
+
+async_badarg(_) ->
+  Path = <<"/project/priv/db_async_badarg">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  Ref = make_ref(),
+  {'EXIT', {badarg, _}} = (catch rocker:get_async(Db, key, Ref)),
+  {'EXIT', {badarg, _}} = (catch rocker:put_async(Db, <<"k">>, v, Ref)),
+  {'EXIT', {badarg, _}} = (catch rocker:multi_get_async(Db, [k], Ref)),
+  receive
+    {rocker_reply, Ref, _} -> error(unexpected_reply)
+  after 100 ->
+    ok
+  end.
+
+async_many(_) ->
+  Path = <<"/project/priv/db_async_many">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  Refs = lists:map(fun(I) ->
+    K = integer_to_binary(I),
+    Ref = make_ref(),
+    ok = rocker:put_async(Db, K, K, Ref),
+    Ref
+                   end, lists:seq(1, 1000)),
+  lists:foreach(fun(Ref) -> ok = await(Ref) end, Refs),
+  {ok, <<"1000">>} = rocker:get(Db, <<"1000">>),
+  ok.
+
+await(Ref) ->
+  receive
+    {rocker_reply, Ref, Result} -> Result
+  after 5000 ->
+    error(timeout)
+  end.
+
+%% =============================================================================
+%% group: errors
+%% =============================================================================
+error_io(_) ->
+  Path = <<"/project/priv/db_error_io">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {error, {io_error, Msg}} = rocker:open_default(Path),
+  true = is_binary(Msg),
+  true = is_reference(Db),
+  ok.
+
+error_invalid_argument(_) ->
+  Path = <<"/project/priv/db_error_invalid_argument">>,
+  rocker:destroy(Path),
+  {error, {invalid_argument, _}} = rocker:open(Path, #{
+    create_if_missing => false
+  }),
+  {ok, Db} = rocker:open_default(Path),
+  {error, {invalid_argument, _}} =
+    rocker:apply_batch_data(Db, 1, <<"short">>),
+  ok.
+
+error_corruption(_) ->
+  Path = <<"/project/priv/db_error_corruption">>,
+  rocker:destroy(Path),
+  Self = self(),
+  spawn(fun() ->
+    {ok, Db} = rocker:open_default(Path),
+    ok = rocker:put(Db, <<"k1">>, <<"not a term">>),
+    Self ! ok
+        end),
+  receive
+    ok ->
+      {ok, Db} = rocker:open(Path, #{value_codec => term}),
+      {error, {corruption, _}} = rocker:get(Db, <<"k1">>),
+      {ok, [{error, {corruption, _}}]} = rocker:multi_get(Db, [<<"k1">>])
+  end,
+  ok.
+
+%% error_format is read when the NIF loads, so it's checked on its own node
+error_legacy(_) ->
+  Path = <<"/project/priv/db_error_legacy">>,
+  rocker:destroy(Path),
+  Node = start_node(rocker_legacy, [{error_format, legacy}]),
+  try
+    ok = rpc:call(Node, ?MODULE, legacy_errors, [Path])
+  after
+    slave:stop(Node)
+  end,
+  ok.
+
+legacy_errors(Path) ->
+  {ok, Db} = rocker:open_default(Path),
+  {err, Msg} = rocker:open_default(Path),
+  true = is_binary(Msg),
+  {err, {unknown_option, foo}} = rocker:open(Path, #{foo => 1}),
+  Cf = <<"nocf">>,
+  {err, {unknown_cf, Cf}} = rocker:put_cf(Db, Cf, <<"k">>, <<"v">>),
+  {err, {unknown_cf, Cf}} = rocker:get_cf(Db, Cf, <<"k">>),
+  {err, {unknown_cf, 1, Cf}} = rocker:tx(Db, [{put_cf, Cf, <<"k">>, <<"v">>}]),
+  ok = rocker:put(Db, <<"k">>, <<"v">>),
+  {ok, <<"v">>} = rocker:get(Db, <<"k">>),
+  ok = rocker:close(Db),
+  {err, closed} = rocker:get(Db, <<"k">>),
+  {err, closed} = rocker:put(Db, <<"k">>, <<"v">>),
+  {err, closed} = rocker:get_cf(Db, Cf, <<"k">>),
+  ok.
+
+%% =============================================================================
+%% group: close
+%% =============================================================================
+close_reopen(_) ->
+  Path = <<"/project/priv/db_close_reopen">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  {error, {io_error, _}} = rocker:open_default(Path),
+  ok = rocker:close(Db),
+  ok = rocker:close(Db),
+  {ok, Db2} = rocker:open_default(Path),
+  {ok, <<"v1">>} = rocker:get(Db2, <<"k1">>),
+  ok.
+
+close_ops(_) ->
+  Path = <<"/project/priv/db_close_ops">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  ok = rocker:close(Db),
+  {error, closed} = rocker:put(Db, <<"k1">>, <<"v1">>),
+  {error, closed} = rocker:get(Db, <<"k1">>),
+  {error, closed} = rocker:delete(Db, <<"k1">>),
+  {error, closed} = rocker:tx(Db, [{put, <<"k1">>, <<"v1">>}]),
+  {error, closed} = rocker:multi_get(Db, [<<"k1">>]),
+  {error, closed} = rocker:iterator(Db, {'start'}),
+  {error, closed} = rocker:get_cf(Db, <<"testcf">>, <<"k1">>),
+  {error, closed} = rocker:create_cf_default(Db, <<"testcf2">>),
+  {error, closed} = rocker:latest_sequence_number(Db),
+  {ok, Path} = rocker:path(Db),
+  ok.
+
+close_iterators(_) ->
+  Path = <<"/project/priv/db_close_iterators">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  ok = rocker:put(Db, <<"k2">>, <<"v2">>),
+  {ok, Iter} = rocker:iterator(Db, {'start'}),
+  {ok, <<"k1">>, <<"v1">>} = rocker:next(Iter),
+  {ok, Wal} = rocker:get_updates_since(Db, 1),
+  ok = rocker:close(Db),
+  {error, closed} = rocker:next(Iter),
+  {error, closed} = rocker:iterator_valid(Iter),
+  {error, closed} = rocker:wal_next(Wal),
+  ok.
+
+close_flush(_) ->
+  Path = <<"/project/priv/db_close_flush">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  {error, {unknown_option, sync}} = rocker:close(Db, #{sync => true}),
+  {error, {invalid_option, flush, yes}} = rocker:close(Db, #{flush => yes}),
+  ok = rocker:close(Db, #{flush => true}),
+  {ok, Db2} = rocker:open_default(Path),
+  {ok, <<"v1">>} = rocker:get(Db2, <<"k1">>),
+  ok.
+
+close_async(_) ->
+  Path = <<"/project/priv/db_close_async">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:close(Db),
+  Ref = make_ref(),
+  ok = rocker:get_async(Db, <<"k1">>, Ref),
+  {error, closed} = await(Ref),
+  ok.
+
+%% =============================================================================
+%% group: pinned
+%% =============================================================================
+pinned_get(_) ->
+  Path = <<"/project/priv/db_pinned_get">>,
+  rocker:destroy(Path),
+  {ok, Db} = open_pinned(Path),
+  Value = crypto:strong_rand_bytes(4 * 1024 * 1024),
+  ok = rocker:put(Db, <<"k1">>, Value),
+  {ok, Value} = rocker:get(Db, <<"k1">>),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  {ok, <<"v1">>} = rocker:get(Db, <<"k1">>),
+  notfound = rocker:get(Db, <<"k2">>),
+  ok.
+
+pinned_get_cf(_) ->
+  Path = <<"/project/priv/db_pinned_get_cf">>,
+  rocker:destroy(Path),
+  {ok, Db} = open_pinned(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  Value = crypto:strong_rand_bytes(1024 * 1024),
+  ok = rocker:put_cf(Db, <<"testcf">>, <<"k1">>, Value),
+  {ok, Value} = rocker:get_cf(Db, <<"testcf">>, <<"k1">>),
+  notfound = rocker:get_cf(Db, <<"testcf">>, <<"k2">>),
+  ok.
+
+pinned_next(_) ->
+  Path = <<"/project/priv/db_pinned_next">>,
+  rocker:destroy(Path),
+  {ok, Db} = open_pinned(Path),
+  Value = crypto:strong_rand_bytes(1024 * 1024),
+  ok = rocker:put(Db, <<"k1">>, Value),
+  ok = rocker:put(Db, <<"k2">>, <<"v2">>),
+  {ok, Iter} = rocker:iterator(Db, {'start'}),
+  {ok, <<"k1">>, Value} = rocker:next(Iter),
+  {ok, <<"k2">>, <<"v2">>} = rocker:next(Iter),
+  ok = rocker:next(Iter),
+  ok.
+
+pinned_small(_) ->
+  Path = <<"/project/priv/db_pinned_small">>,
+  rocker:destroy(Path),
+  {ok, Db} = open_pinned(Path),
+  Value = binary:copy(<<"v">>, 1023),
+  ok = rocker:put(Db, <<"k1">>, Value),
+  ok = rocker:put(Db, <<"k2">>, <<>>),
+  {ok, Value} = rocker:get(Db, <<"k1">>),
+  {ok, <<>>} = rocker:get(Db, <<"k2">>),
+  ok.
+
+pinned_multi_get(_) ->
+  Path = <<"/project/priv/db_pinned_multi_get">>,
+  rocker:destroy(Path),
+  {ok, Db} = open_pinned(Path),
+  Value = crypto:strong_rand_bytes(1024 * 1024),
+  ok = rocker:put(Db, <<"k1">>, Value),
+  ok = rocker:put(Db, <<"k2">>, <<"v2">>),
+  {ok, [{ok, Value}, {ok, <<"v2">>}, notfound]} =
+    rocker:multi_get(Db, [<<"k1">>, <<"k2">>, <<"k3">>]),
+  Ref = make_ref(),
+  ok = rocker:get_async(Db, <<"k1">>, Ref),
+  {ok, Value} = await(Ref),
+  ok.
+
+pinned_close(_) ->
+  Path = <<"/project/priv/db_pinned_close">>,
+  rocker:destroy(Path),
+  {ok, Db} = open_pinned(Path),
+  Value = crypto:strong_rand_bytes(1024 * 1024),
+  ok = rocker:put(Db, <<"k1">>, Value),
+  Self = self(),
+  Pid = spawn(fun() ->
+    {ok, Pinned} = rocker:get(Db, <<"k1">>),
+    false = rocker:released(Db),
+    ok = rocker:close(Db),
+    {error, closed} = rocker:get(Db, <<"k1">>),
+    %% the binary still holds the files and the lock
+    false = rocker:released(Db),
+    {error, {io_error, _}} = rocker:open_default(Path),
+    Self ! {pinned, Pinned =:= Value}
+  end),
+  Ref = monitor(process, Pid),
+  receive {pinned, true} -> ok after 5000 -> error(timeout) end,
+  receive {'DOWN', Ref, process, Pid, _} -> ok end,
+  %% the db stays open until the binary is collected with its process
+  true = wait_until(fun() -> rocker:released(Db) end, 50),
+  {ok, Db2} = rocker:open_default(Path),
+  {ok, Value} = rocker:get(Db2, <<"k1">>),
+  ok.
+
+%% =============================================================================
+%% group: batch
+%% =============================================================================
+batch_write(_) ->
+  Path = <<"/project/priv/db_batch_write">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k0">>, <<"v0">>),
+  ok = rocker:put(Db, <<"r1">>, <<"v1">>),
+  ok = rocker:put(Db, <<"r2">>, <<"v2">>),
+  {ok, Batch} = rocker:batch_new(),
+  true = is_reference(Batch),
+  {ok, 0} = rocker:batch_count(Batch),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v1">>),
+  ok = rocker:batch_put(Batch, <<"k2">>, <<"v2">>),
+  ok = rocker:batch_delete(Batch, <<"k0">>),
+  ok = rocker:batch_delete_range(Batch, <<"r">>, <<"s">>),
+  ok = rocker:batch_merge(Batch, <<"m1">>, <<"v1">>),
+  {ok, 5} = rocker:batch_count(Batch),
+  {ok, Size} = rocker:batch_data_size(Batch),
+  true = Size > 0,
+  ok = rocker:batch_clear(Batch),
+  {ok, 0} = rocker:batch_count(Batch),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v1">>),
+  ok = rocker:batch_put(Batch, <<"k2">>, <<"v2">>),
+  ok = rocker:batch_delete(Batch, <<"k0">>),
+  ok = rocker:batch_delete_range(Batch, <<"r">>, <<"s">>),
+  notfound = rocker:get(Db, <<"k1">>),
+  ok = rocker:write_batch(Db, Batch, #{}),
+  notfound = rocker:get(Db, <<"k0">>),
+  {ok, <<"v1">>} = rocker:get(Db, <<"k1">>),
+  {ok, <<"v2">>} = rocker:get(Db, <<"k2">>),
+  notfound = rocker:get(Db, <<"r1">>),
+  notfound = rocker:get(Db, <<"r2">>),
+  {'EXIT', {badarg, _}} = (catch rocker:batch_put(Batch, k1, <<"v1">>)),
+  ok.
+
+batch_cf(_) ->
+  Path = <<"/project/priv/db_batch_cf">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  ok = rocker:put_cf(Db, <<"testcf">>, <<"k0">>, <<"v0">>),
+  {ok, Cf} = rocker:cf_handle(Db, <<"testcf">>),
+  {ok, Batch} = rocker:batch_new(),
+  ok = rocker:batch_put_cf(Batch, Cf, <<"k1">>, <<"v1">>),
+  ok = rocker:batch_delete_cf(Batch, Cf, <<"k0">>),
+  {'EXIT', {badarg, _}} =
+    (catch rocker:batch_put_cf(Batch, <<"testcf">>, <<"k1">>, <<"v1">>)),
+  ok = rocker:write_batch(Db, Batch, #{}),
+  {ok, <<"v1">>} = rocker:get_cf(Db, <<"testcf">>, <<"k1">>),
+  notfound = rocker:get_cf(Db, <<"testcf">>, <<"k0">>),
+  {ok, Batch2} = rocker:batch_new(),
+  ok = rocker:drop_cf(Db, <<"testcf">>),
+  {error, cf_dropped} = rocker:batch_put_cf(Batch2, Cf, <<"k1">>, <<"v1">>),
+  ok.
+
+batch_savepoint(_) ->
+  Path = <<"/project/priv/db_batch_savepoint">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, Batch} = rocker:batch_new(),
+  {error, {not_found, _}} = rocker:batch_rollback_to_savepoint(Batch),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v1">>),
+  ok = rocker:batch_set_savepoint(Batch),
+  ok = rocker:batch_put(Batch, <<"k2">>, <<"v2">>),
+  ok = rocker:batch_delete(Batch, <<"k1">>),
+  {ok, 3} = rocker:batch_count(Batch),
+  ok = rocker:batch_rollback_to_savepoint(Batch),
+  {ok, 1} = rocker:batch_count(Batch),
+  ok = rocker:write_batch(Db, Batch, #{}),
+  {ok, <<"v1">>} = rocker:get(Db, <<"k1">>),
+  notfound = rocker:get(Db, <<"k2">>),
+  ok.
+
+batch_write_opts(_) ->
+  Path = <<"/project/priv/db_batch_write_opts">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, Batch} = rocker:batch_new(),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v1">>),
+  {error, {unknown_option, fsync}} =
+    rocker:write_batch(Db, Batch, #{fsync => true}),
+  {error, {invalid_option, sync, yes}} =
+    rocker:write_batch(Db, Batch, #{sync => yes}),
+  {ok, 1} = rocker:batch_count(Batch),
+  ok = rocker:write_batch(Db, Batch, #{sync => true, disable_wal => false}),
+  {ok, <<"v1">>} = rocker:get(Db, <<"k1">>),
+  ok.
+
+batch_codecs(_) ->
+  Path = <<"/project/priv/db_batch_codecs">>,
+  KeyPath = <<"/project/priv/db_batch_codecs_key">>,
+  CfPath = <<"/project/priv/db_batch_codecs_cf">>,
+  rocker:destroy(Path),
+  rocker:destroy(KeyPath),
+  rocker:destroy(CfPath),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    value_codec => term
+  }),
+  {ok, Batch} = rocker:batch_new(),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v1">>),
+  %% the binary would be read back as a broken term
+  {error, {not_supported, _}} = rocker:write_batch(Db, Batch, #{}),
+  ok = rocker:tx_set_name(Batch, <<"t1">>),
+  {error, {not_supported, _}} = rocker:tx_prepare(Db, Batch),
+  {ok, Indexed} = rocker:wbwi_new(),
+  {error, {not_supported, _}} = rocker:wbwi_get(Indexed, Db, <<"k1">>),
+  {error, {not_supported, _}} =
+    rocker:wbwi_iterator(Indexed, Db, {'start'}),
+  notfound = rocker:get(Db, <<"k1">>),
+  {ok, 1} = rocker:batch_count(Batch),
+  {ok, KeyDb} = rocker:open(KeyPath, #{
+    create_if_missing => true,
+    key_codec => sortable_term
+  }),
+  {error, {not_supported, _}} = rocker:write_batch(KeyDb, Batch, #{}),
+  %% a cf with a codec of its own on a raw db
+  {ok, CfDb} = rocker:open_default(CfPath),
+  ok = rocker:create_cf(CfDb, <<"testcf">>, #{value_codec => zstd}),
+  {ok, Cf} = rocker:cf_handle(CfDb, <<"testcf">>),
+  {ok, CfBatch} = rocker:batch_new(),
+  {error, {not_supported, _}} =
+    rocker:batch_put_cf(CfBatch, Cf, <<"k1">>, <<"v1">>),
+  {ok, 0} = rocker:batch_count(CfBatch),
+  ok.
+
+batch_consumed(_) ->
+  Path = <<"/project/priv/db_batch_consumed">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, Batch} = rocker:batch_new(),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v1">>),
+  ok = rocker:close(Db),
+  {error, closed} = rocker:write_batch(Db, Batch, #{}),
+  {ok, 1} = rocker:batch_count(Batch),
+  {ok, Db2} = rocker:open_default(Path),
+  ok = rocker:write_batch(Db2, Batch, #{}),
+  {error, consumed} = rocker:write_batch(Db2, Batch, #{}),
+  {error, consumed} = rocker:batch_put(Batch, <<"k2">>, <<"v2">>),
+  {error, consumed} = rocker:batch_count(Batch),
+  {ok, <<"v1">>} = rocker:get(Db2, <<"k1">>),
+  ok.
+
+batch_other_db(_) ->
+  Path = <<"/project/priv/db_batch_other_db">>,
+  OtherPath = <<"/project/priv/db_batch_other_db2">>,
+  rocker:destroy(Path),
+  rocker:destroy(OtherPath),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, Other} = rocker:open_default(OtherPath),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  ok = rocker:create_cf_default(Other, <<"testcf">>),
+  {ok, Cf} = rocker:cf_handle(Db, <<"testcf">>),
+  {ok, OtherCf} = rocker:cf_handle(Other, <<"testcf">>),
+  {ok, Batch} = rocker:batch_new(),
+  ok = rocker:batch_put_cf(Batch, Cf, <<"k1">>, <<"v1">>),
+  {'EXIT', {badarg, _}} =
+    (catch rocker:batch_put_cf(Batch, OtherCf, <<"k1">>, <<"v1">>)),
+  {'EXIT', {badarg, _}} = (catch rocker:write_batch(Other, Batch, #{})),
+  ok = rocker:write_batch(Db, Batch, #{}),
+  ok.
+
+wbwi_get(_) ->
+  Path = <<"/project/priv/db_wbwi_get">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k0">>, <<"v0">>),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  {ok, Batch} = rocker:wbwi_new(),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v11">>),
+  ok = rocker:batch_put(Batch, <<"k2">>, <<"v2">>),
+  ok = rocker:batch_delete(Batch, <<"k0">>),
+  notfound = rocker:wbwi_get(Batch, Db, <<"k0">>),
+  {ok, <<"v11">>} = rocker:wbwi_get(Batch, Db, <<"k1">>),
+  {ok, <<"v2">>} = rocker:wbwi_get(Batch, Db, <<"k2">>),
+  notfound = rocker:wbwi_get(Batch, Db, <<"k3">>),
+  ok = rocker:put(Db, <<"k3">>, <<"v3">>),
+  {ok, <<"v3">>} = rocker:wbwi_get(Batch, Db, <<"k3">>),
+  {ok, <<"v0">>} = rocker:get(Db, <<"k0">>),
+  {ok, <<"v1">>} = rocker:get(Db, <<"k1">>),
+  {ok, 3} = rocker:batch_count(Batch),
+  ok = rocker:write_batch(Db, Batch, #{}),
+  notfound = rocker:get(Db, <<"k0">>),
+  {ok, <<"v11">>} = rocker:get(Db, <<"k1">>),
+  {ok, <<"v2">>} = rocker:get(Db, <<"k2">>),
+  {error, consumed} = rocker:wbwi_get(Batch, Db, <<"k1">>),
+  {ok, Plain} = rocker:batch_new(),
+  {'EXIT', {badarg, _}} = (catch rocker:wbwi_get(Plain, Db, <<"k1">>)),
+  ok.
+
+wbwi_iterator(_) ->
+  Path = <<"/project/priv/db_wbwi_iterator">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  ok = rocker:put(Db, <<"k3">>, <<"v3">>),
+  ok = rocker:put(Db, <<"k5">>, <<"v5">>),
+  {ok, Batch} = rocker:wbwi_new(),
+  ok = rocker:batch_put(Batch, <<"k0">>, <<"v0">>),
+  ok = rocker:batch_put(Batch, <<"k3">>, <<"v33">>),
+  ok = rocker:batch_delete(Batch, <<"k5">>),
+  ok = rocker:batch_put(Batch, <<"k6">>, <<"v6">>),
+  {ok, Iter} = rocker:wbwi_iterator(Batch, Db, {'start'}),
+  {ok, true} = rocker:iterator_valid(Iter),
+  %% later writes to the batch aren't seen by the iterator
+  ok = rocker:batch_put(Batch, <<"k4">>, <<"v4">>),
+  {ok, <<"k0">>, <<"v0">>} = rocker:next(Iter),
+  {ok, <<"k1">>, <<"v1">>} = rocker:next(Iter),
+  {ok, <<"k3">>, <<"v33">>} = rocker:next(Iter),
+  {ok, <<"k6">>, <<"v6">>} = rocker:next(Iter),
+  ok = rocker:next(Iter),
+  {ok, false} = rocker:iterator_valid(Iter),
+  {ok, <<"v5">>} = rocker:get(Db, <<"k5">>),
+  ok = rocker:write_batch(Db, Batch, #{}),
+  {ok, Iter2} = rocker:iterator(Db, {'start'}),
+  {ok, <<"k0">>, <<"v0">>} = rocker:next(Iter2),
+  {ok, <<"k1">>, <<"v1">>} = rocker:next(Iter2),
+  {ok, <<"k3">>, <<"v33">>} = rocker:next(Iter2),
+  {ok, <<"k4">>, <<"v4">>} = rocker:next(Iter2),
+  {ok, <<"k6">>, <<"v6">>} = rocker:next(Iter2),
+  ok = rocker:next(Iter2),
+  {error, consumed} = rocker:wbwi_iterator(Batch, Db, {'start'}),
+  ok.
+
+wbwi_iterator_modes(_) ->
+  Path = <<"/project/priv/db_wbwi_iterator_modes">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k1">>, <<"v1">>),
+  ok = rocker:put(Db, <<"k3">>, <<"v3">>),
+  {ok, Batch} = rocker:wbwi_new(),
+  ok = rocker:batch_put(Batch, <<"k2">>, <<"v2">>),
+  ok = rocker:batch_delete(Batch, <<"k3">>),
+  ok = rocker:batch_put(Batch, <<"k4">>, <<"v4">>),
+  {ok, End} = rocker:wbwi_iterator(Batch, Db, {'end'}),
+  {ok, <<"k4">>, <<"v4">>} = rocker:next(End),
+  {ok, <<"k2">>, <<"v2">>} = rocker:next(End),
+  {ok, <<"k1">>, <<"v1">>} = rocker:next(End),
+  ok = rocker:next(End),
+  {ok, Forward} = rocker:wbwi_iterator(Batch, Db,
+    {'from', <<"k2">>, forward}),
+  {ok, <<"k2">>, <<"v2">>} = rocker:next(Forward),
+  {ok, <<"k4">>, <<"v4">>} = rocker:next(Forward),
+  ok = rocker:next(Forward),
+  {ok, Reverse} = rocker:wbwi_iterator(Batch, Db,
+    {'from', <<"k3">>, reverse}),
+  {ok, <<"k2">>, <<"v2">>} = rocker:next(Reverse),
+  {ok, <<"k1">>, <<"v1">>} = rocker:next(Reverse),
+  ok = rocker:next(Reverse),
+  ok = rocker:close(Db),
+  {error, closed} = rocker:next(Reverse),
+  ok.
+
+wbwi_savepoint(_) ->
+  Path = <<"/project/priv/db_wbwi_savepoint">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k0">>, <<"v0">>),
+  {ok, Batch} = rocker:wbwi_new(),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v1">>),
+  ok = rocker:batch_set_savepoint(Batch),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v11">>),
+  ok = rocker:batch_put(Batch, <<"k2">>, <<"v2">>),
+  ok = rocker:batch_delete(Batch, <<"k0">>),
+  {ok, <<"v11">>} = rocker:wbwi_get(Batch, Db, <<"k1">>),
+  notfound = rocker:wbwi_get(Batch, Db, <<"k0">>),
+  ok = rocker:batch_rollback_to_savepoint(Batch),
+  {ok, <<"v1">>} = rocker:wbwi_get(Batch, Db, <<"k1">>),
+  notfound = rocker:wbwi_get(Batch, Db, <<"k2">>),
+  {ok, <<"v0">>} = rocker:wbwi_get(Batch, Db, <<"k0">>),
+  ok = rocker:batch_clear(Batch),
+  notfound = rocker:wbwi_get(Batch, Db, <<"k1">>),
+  ok.
+
+wbwi_unsupported(_) ->
+  {ok, Batch} = rocker:wbwi_new(),
+  {error, {not_supported, _}} = rocker:batch_merge(Batch, <<"k">>, <<"v">>),
+  {error, {not_supported, _}} =
+    rocker:batch_delete_range(Batch, <<"a">>, <<"b">>),
+  {ok, 0} = rocker:batch_count(Batch),
+  ok.
+
+tx_savepoint(_) ->
+  Path = <<"/project/priv/db_tx_savepoint">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:put(Db, <<"k0">>, <<"v0">>),
+  ok = rocker:put(Db, <<"k3">>, <<"v3">>),
+  {ok, Batch} = rocker:batch_new(),
+  {error, {not_found, _}} = rocker:tx_rollback_to_savepoint(Batch),
+  {error, {not_found, _}} = rocker:tx_pop_savepoint(Batch),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v1">>),
+  ok = rocker:batch_delete(Batch, <<"k0">>),
+  ok = rocker:tx_set_savepoint(Batch),
+  ok = rocker:batch_put(Batch, <<"k2">>, <<"v2">>),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v11">>),
+  ok = rocker:batch_delete(Batch, <<"k3">>),
+  ok = rocker:tx_rollback_to_savepoint(Batch),
+  {ok, 2} = rocker:batch_count(Batch),
+  ok = rocker:write_batch(Db, Batch, #{}),
+  notfound = rocker:get(Db, <<"k0">>),
+  {ok, <<"v1">>} = rocker:get(Db, <<"k1">>),
+  notfound = rocker:get(Db, <<"k2">>),
+  {ok, <<"v3">>} = rocker:get(Db, <<"k3">>),
+  ok.
+
+tx_savepoint_nested(_) ->
+  Path = <<"/project/priv/db_tx_savepoint_nested">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, Batch} = rocker:wbwi_new(),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v1">>),
+  ok = rocker:tx_set_savepoint(Batch),
+  ok = rocker:batch_put(Batch, <<"k2">>, <<"v2">>),
+  ok = rocker:tx_set_savepoint(Batch),
+  ok = rocker:batch_put(Batch, <<"k3">>, <<"v3">>),
+  ok = rocker:tx_rollback_to_savepoint(Batch),
+  notfound = rocker:wbwi_get(Batch, Db, <<"k3">>),
+  {ok, <<"v2">>} = rocker:wbwi_get(Batch, Db, <<"k2">>),
+  ok = rocker:tx_rollback_to_savepoint(Batch),
+  notfound = rocker:wbwi_get(Batch, Db, <<"k2">>),
+  {error, {not_found, _}} = rocker:tx_rollback_to_savepoint(Batch),
+  ok = rocker:write_batch(Db, Batch, #{}),
+  {ok, <<"v1">>} = rocker:get(Db, <<"k1">>),
+  notfound = rocker:get(Db, <<"k2">>),
+  notfound = rocker:get(Db, <<"k3">>),
+  ok.
+
+tx_pop_savepoint(_) ->
+  Path = <<"/project/priv/db_tx_pop_savepoint">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, Batch} = rocker:wbwi_new(),
+  ok = rocker:tx_set_savepoint(Batch),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v1">>),
+  ok = rocker:tx_set_savepoint(Batch),
+  ok = rocker:batch_put(Batch, <<"k2">>, <<"v2">>),
+  %% the inner step is kept and now undone with the outer one
+  ok = rocker:tx_pop_savepoint(Batch),
+  ok = rocker:batch_put(Batch, <<"k3">>, <<"v3">>),
+  ok = rocker:tx_rollback_to_savepoint(Batch),
+  {ok, 0} = rocker:batch_count(Batch),
+  notfound = rocker:wbwi_get(Batch, Db, <<"k2">>),
+  ok = rocker:batch_put(Batch, <<"k4">>, <<"v4">>),
+  ok = rocker:tx_set_savepoint(Batch),
+  ok = rocker:batch_put(Batch, <<"k5">>, <<"v5">>),
+  ok = rocker:tx_pop_savepoint(Batch),
+  {error, {not_found, _}} = rocker:tx_rollback_to_savepoint(Batch),
+  ok = rocker:write_batch(Db, Batch, #{}),
+  notfound = rocker:get(Db, <<"k1">>),
+  notfound = rocker:get(Db, <<"k3">>),
+  {ok, <<"v4">>} = rocker:get(Db, <<"k4">>),
+  {ok, <<"v5">>} = rocker:get(Db, <<"k5">>),
+  ok.
+
+tx_prepare_commit(_) ->
+  Path = <<"/project/priv/db_tx_prepare_commit">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, Batch} = rocker:batch_new(),
+  ok = rocker:batch_put(Batch, <<"k1">>, <<"v1">>),
+  ok = rocker:tx_set_name(Batch, <<"t1">>),
+  ok = rocker:tx_prepare(Db, Batch),
+  {error, prepared} = rocker:batch_put(Batch, <<"k2">>, <<"v2">>),
+  notfound = rocker:get(Db, <<"k1">>),
+  {ok, [{<<"t1">>, _}]} = rocker:get_all_prepared_transactions(Db),
+  %% the record isn't in the keys, the counts or the log of the db
+  {ok, Iter} = rocker:iterator(Db, {'start'}),
+  [] = drain(Iter),
+  {ok, 0} = rocker:estimate_num_keys(Db),
+  {ok, [<<"default">>]} = rocker:list_cf(Path),
+  {ok, Wal0} = rocker:get_updates_since(Db, 1),
+  [] = drain_wal(Wal0),
+  ok = rocker:tx_commit(Db, Batch),
+  {ok, <<"v1">>} = rocker:get(Db, <<"k1">>),
+  {ok, []} = rocker:get_all_prepared_transactions(Db),
+  {error, consumed} = rocker:tx_commit(Db, Batch),
+  %% the record is deleted by the write of the batch, which is all
+  %% that's shown of it
+  {ok, Wal} = rocker:get_updates_since(Db, 1),
+  [{_, [{put, 0, <<"k1">>, <<"v1">>}]}] = drain_wal(Wal),
+  ok.
+
+%% the db is prepared on another node that's killed without closing it
+tx_prepare_recover(_) ->
+  Path = <<"/project/priv/db_tx_prepare_recover">>,
+  rocker:destroy(Path),
+  Node = start_node(rocker_prepare, []),
+  {badrpc, nodedown} = rpc:call(Node, ?MODULE, prepare_and_kill, [Path]),
+  {ok, Db2} = open_retry(Path, 50),
+  {ok, [{<<"t1">>, T1}, {<<"t2">>, T2}]} =
+    rocker:get_all_prepared_transactions(Db2),
+  {error, prepared} = rocker:batch_delete(T1, <<"t1">>),
+  ok = rocker:tx_commit(Db2, T1),
+  ok = rocker:tx_rollback(Db2, T2),
+  {error, consumed} = rocker:tx_commit(Db2, T2),
+  ok = rocker:close(Db2),
+  {ok, Db3} = rocker:open_default(Path),
+  {ok, []} = rocker:get_all_prepared_transactions(Db3),
+  {ok, <<"v">>} = rocker:get(Db3, <<"t1">>),
+  notfound = rocker:get(Db3, <<"t2">>),
+  {ok, [<<"default">>]} = rocker:list_cf(Path),
+  ok.
+
+prepare_and_kill(Path) ->
+  {ok, Db} = rocker:open_default(Path),
+  lists:foreach(fun(Name) ->
+    {ok, Batch} = rocker:batch_new(),
+    ok = rocker:batch_put(Batch, Name, <<"v">>),
+    ok = rocker:tx_set_name(Batch, Name),
+    ok = rocker:tx_prepare(Db, Batch)
+  end, [<<"t1">>, <<"t2">>]),
+  os:cmd("kill -9 " ++ os:getpid()).
+
+tx_prepare_errors(_) ->
+  Path = <<"/project/priv/db_tx_prepare_errors">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, Batch} = rocker:batch_new(),
+  {error, {invalid_argument, _}} = rocker:tx_prepare(Db, Batch),
+  ok = rocker:tx_set_name(Batch, <<"t1">>),
+  {error, {invalid_argument, _}} = rocker:tx_set_name(Batch, <<"t2">>),
+  ok = rocker:tx_prepare(Db, Batch),
+  {error, prepared} = rocker:tx_prepare(Db, Batch),
+  {ok, Other} = rocker:batch_new(),
+  {'EXIT', {badarg, _}} = (catch rocker:tx_set_name(Other, <<>>)),
+  ok = rocker:tx_set_name(Other, <<"t1">>),
+  {error, {invalid_argument, _}} = rocker:tx_prepare(Db, Other),
+  %% an unprepared batch is just dropped
+  ok = rocker:tx_rollback(Db, Other),
+  {error, consumed} = rocker:tx_set_name(Other, <<"t3">>),
+  ok = rocker:tx_rollback(Db, Batch),
+  {ok, []} = rocker:get_all_prepared_transactions(Db),
+  ok.
+
+open_pinned(Path) ->
+  rocker:open(Path, #{
+    create_if_missing => true,
+    resource_binary_threshold => 1024
+  }).
+
+%% a slave node with the code paths of this one and rocker env Env
+start_node(Name, Env) ->
+  case node() of
+    nonode@nohost -> {ok, _} = net_kernel:start(['ct@127.0.0.1', longnames]);
+    _ -> ok
+  end,
+  {ok, Node} = slave:start("127.0.0.1", Name),
+  true = rpc:call(Node, code, set_path, [code:get_path()]),
+  ok = rpc:call(Node, application, load, [rocker]),
+  [ok = rpc:call(Node, application, set_env, [rocker, K, V]) || {K, V} <- Env],
+  Node.
+
+open_retry(Path, 0) ->
+  rocker:open_default(Path);
+open_retry(Path, N) ->
+  case rocker:open_default(Path) of
+    {ok, Db} -> {ok, Db};
+    {error, {io_error, _}} ->
+      timer:sleep(100),
+      open_retry(Path, N - 1)
+  end.
+
+%% =============================================================================
+%% group: stats
+%% =============================================================================
+estimate_num_keys(_) ->
+  Path = <<"/project/priv/db_estimate_num_keys">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, 0} = rocker:estimate_num_keys(Db),
+  [ok = rocker:put(Db, <<N:32>>, <<"v">>) || N <- lists:seq(1, 1000)],
+  {ok, Keys} = rocker:estimate_num_keys(Db),
+  true = Keys > 0,
+  ok = rocker:close(Db),
+  {error, closed} = rocker:estimate_num_keys(Db),
+  ok.
+
+estimate_num_keys_cf(_) ->
+  Path = <<"/project/priv/db_estimate_num_keys_cf">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  {ok, Cf} = rocker:cf_handle(Db, <<"testcf">>),
+  [ok = rocker:put_cf(Db, Cf, <<N:32>>, <<"v">>) || N <- lists:seq(1, 100)],
+  {ok, Keys} = rocker:estimate_num_keys_cf(Db, <<"testcf">>),
+  true = Keys > 0,
+  {ok, Keys} = rocker:estimate_num_keys_cf(Db, Cf),
+  {ok, 0} = rocker:estimate_num_keys(Db),
+  {error, {unknown_cf, <<"nocf">>}} =
+    rocker:estimate_num_keys_cf(Db, <<"nocf">>),
+  ok.
+
+approximate_sizes(_) ->
+  Path = <<"/project/priv/db_approximate_sizes">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  Value = binary:copy(<<"v">>, 100),
+  [ok = rocker:put(Db, <<N:32>>, Value) || N <- lists:seq(1, 1000)],
+  ok = rocker:close(Db, #{flush => true}),
+  {ok, Db2} = rocker:open_default(Path),
+  All = {<<0:32>>, <<1001:32>>},
+  {ok, [Size, 0, 0]} = rocker:approximate_sizes(Db2, [
+    All,
+    {<<2000:32>>, <<3000:32>>},
+    {<<500:32>>, <<500:32>>}
+  ], #{}),
+  true = Size > 1000 * 100 div 2,
+  {ok, [Size]} = rocker:approximate_sizes(Db2, [{<<1:32>>, <<2:32>>}], #{}),
+  %% only the memtables see the key
+  ok = rocker:put(Db2, <<5000:32>>, Value),
+  Memtables = #{include_memtables => true, include_files => false},
+  {ok, [InMemtables, 0]} = rocker:approximate_sizes(Db2,
+    [{<<5000:32>>, <<5001:32>>}, {<<1:32>>, <<1:32>>}], Memtables),
+  true = InMemtables > 0,
+  {ok, [Both]} = rocker:approximate_sizes(Db2, [All],
+    #{include_memtables => true}),
+  Both = Size + InMemtables,
+  {error, {invalid_argument, _}} = rocker:approximate_sizes(Db2, [All],
+    #{include_files => false}),
+  {error, {unknown_option, files}} =
+    rocker:approximate_sizes(Db2, [All], #{files => true}),
+  {'EXIT', {badarg, _}} = (catch rocker:approximate_sizes(Db2, [a], #{})),
+  ok.
+
+approximate_sizes_cf(_) ->
+  Path = <<"/project/priv/db_approximate_sizes_cf">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  [ok = rocker:put_cf(Db, <<"testcf">>, <<N:32>>, <<"v">>)
+    || N <- lists:seq(1, 1000)],
+  ok = rocker:close(Db, #{flush => true}),
+  {ok, Db2} = rocker:open_cf_default(Path, [<<"testcf">>]),
+  {ok, Cf} = rocker:cf_handle(Db2, <<"testcf">>),
+  Range = {<<0:32>>, <<1001:32>>},
+  {ok, [Size]} = rocker:approximate_sizes_cf(Db2, <<"testcf">>, [Range], #{}),
+  true = Size > 0,
+  {ok, [Size]} = rocker:approximate_sizes_cf(Db2, Cf, [Range], #{}),
+  {ok, [0]} = rocker:approximate_sizes(Db2, [Range], #{}),
+  {error, {unknown_cf, <<"nocf">>}} =
+    rocker:approximate_sizes_cf(Db2, <<"nocf">>, [Range], #{}),
+  ok.
+
+column_family_metadata(_) ->
+  Path = <<"/project/priv/db_column_family_metadata">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, #{name := <<"default">>, size := 0, levels := Empty}} =
+    rocker:column_family_metadata(Db, default),
+  [#{level := 0, files := 0} | _] = Empty,
+  [ok = rocker:put(Db, <<N:32>>, <<"v">>) || N <- lists:seq(1, 1000)],
+  ok = rocker:close(Db, #{flush => true}),
+  {ok, Db2} = rocker:open_default(Path),
+  {ok, #{size := Size, levels := Levels}} =
+    rocker:column_family_metadata(Db2, default),
+  true = Size > 0,
+  Size = lists:sum([S || #{size := S} <- Levels]),
+  1000 = lists:sum([E || #{entries := E} <- Levels]),
+  true = lists:sum([F || #{files := F} <- Levels]) > 0,
+  %% the files of every level add up to its stats
+  [] = [L || #{level := L, files := F, size := S, sst_files := Files} <- Levels,
+    {F, S} =/= {length(Files), lists:sum([FS || #{size := FS} <- Files])}],
+  Files = lists:append([Fs || #{sst_files := Fs} <- Levels]),
+  <<1:32>> = lists:min([K || #{smallest_key := K} <- Files]),
+  <<1000:32>> = lists:max([K || #{largest_key := K} <- Files]),
+  [<<"/", _/binary>> = Name || #{name := Name} <- Files],
+  ok.
+
+column_family_metadata_cf(_) ->
+  Path = <<"/project/priv/db_column_family_metadata_cf">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  {ok, Cf} = rocker:cf_handle(Db, <<"testcf">>),
+  {ok, #{name := <<"testcf">>, levels := [_ | _]}} =
+    rocker:column_family_metadata(Db, <<"testcf">>),
+  {ok, #{name := <<"testcf">>}} = rocker:column_family_metadata(Db, Cf),
+  {error, {unknown_cf, <<"nocf">>}} =
+    rocker:column_family_metadata(Db, <<"nocf">>),
+  {'EXIT', {badarg, _}} = (catch rocker:column_family_metadata(Db, other)),
+  ok.
+
+live_files(_) ->
+  Path = <<"/project/priv/db_live_files">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  {ok, []} = rocker:live_files(Db),
+  ok = rocker:create_cf_default(Db, <<"testcf">>),
+  ok = rocker:put(Db, <<"a">>, <<"v">>),
+  ok = rocker:put_cf(Db, <<"testcf">>, <<"b">>, <<"v">>),
+  %% the internal cf isn't listed
+  {ok, Follower} = rocker:write_batch_data([{put, 0, <<"c">>, <<"v">>}]),
+  ok = rocker:apply_batch_data(Db, 1, Follower),
+  ok = rocker:close(Db, #{flush => true}),
+  {ok, Db2} = rocker:open_cf_default(Path, [<<"testcf">>]),
+  {ok, Files} = rocker:live_files(Db2),
+  [#{column_family := <<"default">>, level := _, size := Size,
+    smallest_key := <<"a">>, largest_key := <<"c">>},
+    #{column_family := <<"testcf">>, smallest_key := <<"b">>,
+      largest_key := <<"b">>}] = Files,
+  true = Size > 0,
+  {ok, #{levels := Levels}} = rocker:column_family_metadata(Db2, default),
+  [File] = lists:append([Fs || #{sst_files := Fs} <- Levels]),
+  [File] = [maps:remove(column_family, F) || F <- Files,
+    maps:get(column_family, F) =:= <<"default">>],
+  ok.
+
+event_listener_flush(_) ->
+  Path = <<"/project/priv/db_event_listener_flush">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    event_listener => self()
+  }),
+  %% a cf created later is polled too
+  ok = rocker:create_cf(Db, <<"testcf">>,
+    #{set_write_buffer_size => 64 * 1024}),
+  V = binary:copy(<<"v">>, 1024),
+  [ok = rocker:put_cf(Db, <<"testcf">>, <<N:32>>, V) ||
+    N <- lists:seq(1, 200)],
+  receive
+    {rocker_event, Db, flush_completed, #{column_family := <<"testcf">>,
+      sst_files := [#{level := 0, size := Size} | _]}} ->
+      true = Size > 0
+  after 5000 ->
+    error(no_flush_event)
+  end,
+  %% nothing was flushed in the default cf
+  receive
+    {rocker_event, Db, flush_completed, #{column_family := <<"default">>}} ->
+      error(default_flushed)
+  after 500 ->
+    ok
+  end,
+  ok = rocker:close(Db),
+  true = wait_until(fun() -> rocker:released(Db) end, 100),
+  ok.
+
+%% writes stop at the L0 file limit until compactions catch up
+event_listener_stall(_) ->
+  Path = <<"/project/priv/db_event_listener_stall">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open(Path, #{
+    create_if_missing => true,
+    set_write_buffer_size => 64 * 1024,
+    set_disable_auto_compactions => true,
+    set_level_zero_slowdown_writes_trigger => 2,
+    set_level_zero_stop_writes_trigger => 6,
+    event_listener => self()
+  }),
+  V = binary:copy(<<"v">>, 1024),
+  {Writer, Ref} = spawn_monitor(fun() ->
+    [ok = rocker:put(Db, <<N:32>>, V) || N <- lists:seq(1, 1000)]
+  end),
+  ok = wait_stall(Db, fun(#{write_stopped := Stopped}) -> Stopped end),
+  ok = rocker:set_options(Db, #{set_disable_auto_compactions => false}),
+  ok = wait_stall(Db, fun(#{write_stopped := Stopped}) -> not Stopped end),
+  receive {'DOWN', Ref, process, Writer, normal} -> ok end,
+  ok = rocker:close(Db),
+  ok.
+
+wait_stall(Db, Match) ->
+  receive
+    {rocker_event, Db, stall_conditions_changed, Info} ->
+      case Match(Info) of
+        true -> ok;
+        false -> wait_stall(Db, Match)
+      end
+  after 10000 ->
+    error(no_stall_event)
+  end.
+
+%% =============================================================================
+%% group: perf
+%% =============================================================================
+perf_default(_) ->
+  Path = <<"/project/priv/perf_default">>,
+  rocker:destroy(Path),
+  {ok, Db} = rocker:open_default(Path),
+  W = perftest:comprehensive(1000, fun() ->
+    I = list_to_binary(uuid:uuid_to_string(uuid:get_v4())),
+    ok = rocker:put(Db, I, I)
+                                   end),
+  true = lists:all(fun(E) -> E >= 5000 end, W),
+
+  ok = rocker:put(Db, <<"k0">>, <<"v0">>),
+  R = perftest:comprehensive(1000, fun() ->
+    {ok, <<"v0">>} = rocker:get(Db, <<"k0">>)
+                                   end),
+  true = lists:all(fun(E) -> E >= 5000 end, R),
+
+  case erlang:system_info(schedulers_online) of
+    1 ->
+      %% one scheduler runs one put at a time, there's nothing to scale
+      {skip, "put scaling needs more than one scheduler"};
+    Cores ->
+      W1 = parallel_writes(Db, 1, 20000),
+      WN = parallel_writes(Db, Cores, 20000),
+      ct:pal("put RPS: 1 process ~p, ~p processes ~p", [W1, Cores, WN]),
+      %% writers don't serialize in the nif, RocksDB groups their
+      %% commits, so they must be at least a fifth faster together
+      true = WN * 5 >= W1 * 6,
+      ok
+  end.
+
+%% writes from Procs processes at once, returns the total RPS
+parallel_writes(Db, Procs, PerProc) ->
+  Self = self(),
+  Start = erlang:monotonic_time(microsecond),
+  Pids = [spawn_link(fun() ->
+    lists:foreach(fun(I) ->
+      K = <<(integer_to_binary(P))/binary, "_", (integer_to_binary(I))/binary>>,
+      ok = rocker:put(Db, K, K)
+                  end, lists:seq(1, PerProc)),
+    Self ! {done, self()}
+                     end) || P <- lists:seq(1, Procs)],
+  [receive {done, Pid} -> ok end || Pid <- Pids],
+  Time = erlang:monotonic_time(microsecond) - Start,
+  Procs * PerProc * 1000000 div max(Time, 1).